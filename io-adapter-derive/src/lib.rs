@@ -0,0 +1,233 @@
+//! Derives for the `ReadAdapter` and `WriteAdapter` traits of io-adapter.
+//!
+//! The struct must mark exactly one field with `#[adapter(inner)]`; the type of that field is the
+//! type the struct adapts over. Every other field must implement `Default`, and is set to its
+//! default value when the struct is wrapped. The struct can name a method with
+//! `#[adapter(flush = "method")]`, of type `fn(&mut self) -> Result<(), E>` where `E` converts
+//! into `io_adapter::IoError`; unwrapping calls it before returning the inner field. It can also
+//! name a method with `#[adapter(remaining = "method")]`, of type `fn(&self) -> usize`, to report
+//! how many bytes were left unflushed when the flush fails.
+//!
+//! The generated impls only name items through absolute paths, so they do not depend on what
+//! the deriving crate has in scope.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Data, DeriveInput, Ident, LitStr, Member, Type};
+
+/// Derive `ReadAdapter` for a struct with one field marked `#[adapter(inner)]`.
+///
+/// ```
+/// extern crate io_adapter;
+///
+/// use io_adapter::ReadAdapter;
+///
+/// #[derive(ReadAdapter)]
+/// struct Counting<R> {
+///     #[adapter(inner)]
+///     reader: R,
+///     count: usize,
+/// }
+/// # fn main() {}
+/// ```
+///
+/// A struct without an inner field fails to compile:
+///
+/// ```compile_fail
+/// extern crate io_adapter;
+///
+/// use io_adapter::ReadAdapter;
+///
+/// #[derive(ReadAdapter)]
+/// struct Counting<R> {
+///     reader: R,
+///     count: usize,
+/// }
+/// # fn main() {}
+/// ```
+#[proc_macro_derive(ReadAdapter, attributes(adapter))]
+pub fn derive_read_adapter(input: TokenStream) -> TokenStream {
+    derive(input, quote!(ReadAdapter))
+}
+
+/// Derive `WriteAdapter` for a struct with one field marked `#[adapter(inner)]`.
+///
+/// A struct with more than one inner field fails to compile:
+///
+/// ```compile_fail
+/// extern crate io_adapter;
+///
+/// use io_adapter::WriteAdapter;
+///
+/// #[derive(WriteAdapter)]
+/// struct Tee<W> {
+///     #[adapter(inner)]
+///     first: W,
+///     #[adapter(inner)]
+///     second: W,
+/// }
+/// # fn main() {}
+/// ```
+#[proc_macro_derive(WriteAdapter, attributes(adapter))]
+pub fn derive_write_adapter(input: TokenStream) -> TokenStream {
+    derive(input, quote!(WriteAdapter))
+}
+
+fn derive(input: TokenStream, adapter: TokenStream2) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match Adapter::parse(&input) {
+        Ok(parsed)  => parsed.expand(&input, adapter).into(),
+        Err(error)  => error.to_compile_error().into(),
+    }
+}
+
+struct Adapter<'a> {
+    inner: Member,
+    inner_ty: &'a Type,
+    defaults: Vec<Member>,
+    flush: Option<Ident>,
+    remaining: Option<Ident>,
+}
+
+impl<'a> Adapter<'a> {
+    fn parse(input: &'a DeriveInput) -> syn::Result<Adapter<'a>> {
+        let fields = match input.data {
+            Data::Struct(ref data)  => &data.fields,
+            _                       => {
+                let message = "adapters can only be derived for structs";
+                return Err(syn::Error::new_spanned(input, message))
+            }
+        };
+
+        let mut flush = None;
+        let mut remaining = None;
+        for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("adapter")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("flush") {
+                    flush = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("remaining") {
+                    remaining = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `flush` or `remaining`"))
+                }
+            })?;
+        }
+
+        let mut inner = None;
+        let mut defaults = vec![];
+        for (index, field) in fields.iter().enumerate() {
+            let member = match field.ident {
+                Some(ref ident) => Member::Named(ident.clone()),
+                None            => Member::Unnamed(index.into()),
+            };
+            let mut is_inner = false;
+            for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("adapter")) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("inner") {
+                        is_inner = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `inner`"))
+                    }
+                })?;
+            }
+            if !is_inner {
+                defaults.push(member);
+            } else if inner.is_none() {
+                inner = Some((member, &field.ty));
+            } else {
+                let message = "only one field can be marked `#[adapter(inner)]`";
+                return Err(syn::Error::new_spanned(field, message))
+            }
+        }
+
+        match inner {
+            Some((inner, inner_ty)) => Ok(Adapter { inner, inner_ty, defaults, flush, remaining }),
+            None                    => {
+                Err(syn::Error::new_spanned(input, "one field must be marked `#[adapter(inner)]`"))
+            }
+        }
+    }
+
+    fn expand(&self, input: &DeriveInput, adapter: TokenStream2) -> TokenStream2 {
+        let name = &input.ident;
+        let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+        let inner = &self.inner;
+        let inner_ty = self.inner_ty;
+        let defaults = &self.defaults;
+
+        let unwrap = match self.flush {
+            Some(ref flush) => {
+                let remaining = match self.remaining {
+                    Some(ref remaining) => quote!(self.#remaining()),
+                    None                => quote!(0),
+                };
+                let message = format!("Failed to unwrap {}: {{:?}}", name);
+                quote! {
+                    fn into_inner(self) -> #inner_ty {
+                        match ::io_adapter::#adapter::try_into_inner(self) {
+                            ::std::result::Result::Ok(inner)    => inner,
+                            ::std::result::Result::Err(error)   => {
+                                ::std::panic!(#message, error.error())
+                            }
+                        }
+                    }
+
+                    fn try_into_inner(mut self)
+                        -> ::std::result::Result<#inner_ty, ::io_adapter::UnwrapError<Self>>
+                    {
+                        match self.#flush() {
+                            ::std::result::Result::Ok(())       => {
+                                ::std::result::Result::Ok(self.#inner)
+                            }
+                            ::std::result::Result::Err(error)   => {
+                                let error = ::std::convert::Into::into(error);
+                                let remaining = #remaining;
+                                let error = ::io_adapter::UnwrapError::new(self, error, remaining);
+                                ::std::result::Result::Err(error)
+                            }
+                        }
+                    }
+                }
+            }
+            None            => quote! {
+                fn into_inner(self) -> #inner_ty {
+                    self.#inner
+                }
+            },
+        };
+
+        quote! {
+            impl #impl_generics ::io_adapter::#adapter<#inner_ty> for #name #ty_generics
+                #where_clause
+            {
+                type Config = ();
+
+                fn wrap_with(inner: #inner_ty, _: ()) -> Self {
+                    #name {
+                        #inner: inner,
+                        #(#defaults: ::std::default::Default::default(),)*
+                    }
+                }
+
+                fn get_ref(&self) -> &#inner_ty {
+                    &self.#inner
+                }
+
+                fn get_mut(&mut self) -> &mut #inner_ty {
+                    &mut self.#inner
+                }
+
+                #unwrap
+            }
+        }
+    }
+}
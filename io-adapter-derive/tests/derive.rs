@@ -0,0 +1,101 @@
+extern crate io_adapter;
+
+use std::io::{self, Read, Write};
+
+use io_adapter::{ReadAdapter, WriteAdapter};
+
+// The generated impls must not pick up the names this crate shadows.
+#[allow(dead_code)]
+type Result<T> = std::result::Result<T, ()>;
+
+#[allow(dead_code)]
+trait Default {}
+
+#[derive(ReadAdapter)]
+struct Counting<R> {
+    #[adapter(inner)]
+    reader: R,
+    count: usize,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+}
+
+#[derive(WriteAdapter)]
+#[adapter(flush = "flush_out", remaining = "remaining")]
+struct Buffered<W: Write> {
+    #[adapter(inner)]
+    writer: W,
+    out: Vec<u8>,
+}
+
+impl<W: Write> Buffered<W> {
+    fn flush_out(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.out.len()
+    }
+}
+
+impl<W: Write> Write for Buffered<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+#[test]
+fn read_adapter() {
+    let mut reader = Counting::wrap_with(&b"data"[..], ());
+    assert_eq!(reader.count, 0);
+    assert_eq!(reader.get_ref().len(), 4);
+
+    let mut buf = [0; 3];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(reader.count, 3);
+    *reader.get_mut() = &b"other"[..];
+    assert_eq!(reader.try_into_inner().ok().unwrap(), b"other");
+}
+
+#[test]
+fn write_adapter_flushes_when_unwrapped() {
+    let mut writer = Buffered::wrap(Vec::new());
+    writer.write_all(b"data").unwrap();
+    assert!(writer.get_ref().is_empty());
+    writer.get_mut().push(b'>');
+    assert_eq!(writer.try_into_inner().ok().unwrap(), b">data");
+}
+
+#[test]
+fn write_adapter_unwrap_fails() {
+    let mut buf = [0; 2];
+    let mut writer = Buffered::wrap(&mut buf[..]);
+    writer.write_all(b"data").unwrap();
+    let error = writer.try_into_inner().err().unwrap();
+    assert_eq!(error.error().kind(), io::ErrorKind::WriteZero);
+    assert_eq!(error.remaining(), 4);
+    assert_eq!(error.into_inner().out, b"data");
+}
+
+#[test]
+#[should_panic(expected = "Failed to unwrap Buffered")]
+fn write_adapter_into_inner_panics() {
+    let mut buf = [0; 2];
+    let mut writer = Buffered::wrap(&mut buf[..]);
+    writer.write_all(b"data").unwrap();
+    writer.into_inner();
+}
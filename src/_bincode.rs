@@ -0,0 +1,106 @@
+use std::io::{Read, Write};
+
+use {ReadAdapter, WriteAdapter};
+
+extern crate bincode;
+extern crate serde1 as serde;
+
+/// A bincode serializer over a Write type, which can be unwrapped to get the Write back.
+///
+/// `bincode::Serializer` does not give back the Write it was constructed over, so this
+/// serializes each value with `bincode::serialize_into` instead.
+pub struct BincodeSerializer<W> {
+    writer: W,
+}
+
+impl<W: Write> BincodeSerializer<W> {
+    /// Serialize a value as bincode to the writer.
+    pub fn serialize<T: serde::Serialize>(&mut self, value: &T) -> bincode::Result<()> {
+        bincode::serialize_into(&mut self.writer, value)
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for BincodeSerializer<W> {
+    type Config = ();
+
+    fn wrap_with(writer: W, _: ()) -> Self {
+        BincodeSerializer { writer }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// A bincode deserializer over a Read type, which can be unwrapped to get the Read back.
+///
+/// bincode reads exactly the bytes of each value, so nothing is read past the end of one.
+pub struct BincodeDeserializer<R> {
+    reader: R,
+}
+
+impl<R: Read> BincodeDeserializer<R> {
+    /// Deserialize the next bincode value from the reader.
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&mut self) -> bincode::Result<T> {
+        bincode::deserialize_from(&mut self.reader)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for BincodeDeserializer<R> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        BincodeDeserializer { reader }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {ReadAdapter, WriteAdapter};
+    use super::{BincodeDeserializer, BincodeSerializer};
+
+    #[test]
+    fn round_trip() {
+        let value = (7u32, "seven".to_string(), vec![1u8, 2, 3], Some(true));
+        let mut ser = BincodeSerializer::wrap(Vec::new());
+        ser.serialize(&value).unwrap();
+        let mut encoded = match ser.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap BincodeSerializer: {:?}", error.error()),
+        };
+        encoded.extend_from_slice(b"rest");
+
+        let mut de = BincodeDeserializer::wrap(&encoded[..]);
+        assert_eq!(de.deserialize().ok(), Some(value));
+        // Nothing past the value has been read.
+        match de.try_into_inner() {
+            Ok(reader)  => assert_eq!(reader, b"rest"),
+            Err(error)  => panic!("Failed to unwrap BincodeDeserializer: {:?}", error.error()),
+        }
+
+        // A truncated value fails to deserialize, and the deserializer still unwraps.
+        let mut de = BincodeDeserializer::wrap(&encoded[..4]);
+        assert!(de.deserialize::<(u32, String, Vec<u8>, Option<bool>)>().is_err());
+        assert!(de.try_into_inner().is_ok());
+    }
+}
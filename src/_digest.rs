@@ -0,0 +1,257 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use {ReadAdapter, WriteAdapter};
+
+extern crate digest;
+
+use self::digest::{Digest, Output};
+
+/// A ReadAdapter which feeds every byte read through it to a Digest.
+pub struct HashingReader<R, D> {
+    reader: R,
+    digest: D,
+}
+
+impl<R, D: Digest> HashingReader<R, D> {
+    /// The digest of the bytes read so far.
+    pub fn digest(&self) -> &D {
+        &self.digest
+    }
+
+    /// Unwrap this reader, returning the inner Read and the finished digest of the bytes read
+    /// through it. This hides `ReadAdapter::into_inner`, which only returns the inner Read.
+    pub fn into_inner(self) -> (R, Output<D>) {
+        (self.reader, self.digest.finalize())
+    }
+}
+
+impl<R: Read, D: Digest> Read for HashingReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.digest.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: Read, D: Digest> ReadAdapter<R> for HashingReader<R, D> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        HashingReader { reader, digest: D::new() }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// A WriteAdapter which feeds every byte written through it to a Digest.
+///
+/// Only the bytes the inner Write accepts are fed to the digest.
+pub struct HashingWriter<W, D> {
+    writer: W,
+    digest: D,
+}
+
+impl<W, D: Digest> HashingWriter<W, D> {
+    /// The digest of the bytes written so far.
+    pub fn digest(&self) -> &D {
+        &self.digest
+    }
+
+    /// Unwrap this writer, returning the inner Write and the finished digest of the bytes
+    /// written through it. This hides `WriteAdapter::into_inner`, which only returns the inner
+    /// Write.
+    pub fn into_inner(self) -> (W, Output<D>) {
+        (self.writer, self.digest.finalize())
+    }
+}
+
+impl<W: Write, D: Digest> Write for HashingWriter<W, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.digest.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write, D: Digest> WriteAdapter<W> for HashingWriter<W, D> {
+    type Config = ();
+
+    fn wrap_with(writer: W, _: ()) -> Self {
+        HashingWriter { writer, digest: D::new() }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// A ReadAdapter which checks the bytes read through it against an expected digest.
+///
+/// It is created with the expected digest by `new`. When the inner Read reaches its end, the
+/// digest of everything read is compared with it, and if they differ the read fails with
+/// `ErrorKind::InvalidData` instead of returning 0.
+///
+/// As a ReadAdapter its configuration is the expected digest, and the default configuration
+/// has none, so a reader created by `wrap` is unusable: every read fails with
+/// `ErrorKind::InvalidInput`. Use `new`, or `wrap_with` and `Some` digest.
+pub struct VerifyingReader<R, D: Digest> {
+    reader: R,
+    digest: D,
+    expected: Option<Output<D>>,
+    actual: Option<Output<D>>,
+}
+
+impl<R, D: Digest> VerifyingReader<R, D> {
+    /// Create a reader which checks the bytes read from `reader` against `expected`.
+    pub fn new(reader: R, expected: Output<D>) -> VerifyingReader<R, D> {
+        VerifyingReader { reader, digest: D::new(), expected: Some(expected), actual: None }
+    }
+
+    /// Whether the input has been read to its end and its digest matched the expected one.
+    pub fn is_verified(&self) -> bool {
+        self.actual.is_some() && self.actual == self.expected
+    }
+}
+
+impl<R: Read, D: Digest> Read for VerifyingReader<R, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let expected = match self.expected {
+            Some(ref expected)  => expected,
+            None                => {
+                let error = "VerifyingReader was not configured with an expected digest";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+            }
+        };
+        let n = self.reader.read(buf)?;
+        if n > 0 || buf.is_empty() {
+            self.digest.update(&buf[..n]);
+            return Ok(n);
+        }
+
+        let digest = &mut self.digest;
+        let actual = self.actual.get_or_insert_with(|| mem::replace(digest, D::new()).finalize());
+        if actual == expected {
+            Ok(0)
+        } else {
+            let error = format!("digest mismatch: expected {}, read {}",
+                                hex(expected), hex(actual));
+            Err(io::Error::new(io::ErrorKind::InvalidData, error))
+        }
+    }
+}
+
+impl<R: Read, D: Digest> ReadAdapter<R> for VerifyingReader<R, D> {
+    type Config = Option<Output<D>>;
+
+    fn wrap_with(reader: R, expected: Option<Output<D>>) -> Self {
+        VerifyingReader { reader, digest: D::new(), expected, actual: None }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate sha2;
+
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use self::sha2::{Digest, Sha256};
+    use super::{HashingReader, HashingWriter, VerifyingReader};
+
+    // The SHA-256 of "abc", from FIPS 180-2.
+    const ABC: [u8; 32] = [
+        0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22,
+        0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00,
+        0x15, 0xad,
+    ];
+
+    #[test]
+    fn hashing_writer_known_vector() {
+        let mut writer = HashingWriter::<_, Sha256>::wrap(Vec::new());
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"bc").unwrap();
+        let (written, digest) = writer.into_inner();
+        assert_eq!(written, b"abc");
+        assert_eq!(digest[..], ABC);
+    }
+
+    #[test]
+    fn hashing_reader_known_vector() {
+        let mut reader = HashingReader::<_, Sha256>::wrap(&b"abc"[..]);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(reader.digest().clone().finalize()[..], ABC);
+        let (rest, digest) = reader.into_inner();
+        assert!(rest.is_empty());
+        assert_eq!(digest[..], ABC);
+    }
+
+    #[test]
+    fn matching_digest() {
+        let mut reader = VerifyingReader::<_, Sha256>::new(&b"abc"[..], ABC.into());
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"abc");
+        assert!(reader.is_verified());
+    }
+
+    #[test]
+    fn mismatching_digest_fails_at_end() {
+        let mut reader = VerifyingReader::<_, Sha256>::new(&b"abd"[..], ABC.into());
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert!(!reader.is_verified());
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(!reader.is_verified());
+    }
+
+    #[test]
+    fn wrap_without_digest_fails() {
+        let mut reader = VerifyingReader::<_, Sha256>::wrap(&b"abc"[..]);
+        assert_eq!(reader.read(&mut [0; 4]).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut reader = VerifyingReader::<_, Sha256>::wrap_with(&b"abc"[..], Some(ABC.into()));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(reader.is_verified());
+    }
+}
@@ -0,0 +1,254 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use std::cmp;
+
+use {BufConfig, IoError, ReadAdapter, WriteAdapter, UnwrapError};
+
+extern crate embedded_io;
+
+use self::embedded_io::{BufRead, ErrorKind, ErrorType, Read, Write};
+
+/// A buffered reader over an `embedded_io::Read`, which works without std.
+pub struct EmbeddedBufReader<R> {
+    reader: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+}
+
+impl<R: Read> ErrorType for EmbeddedBufReader<R> {
+    type Error = R::Error;
+}
+
+impl<R: Read> Read for EmbeddedBufReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, R::Error> {
+        // Bypass the buffer entirely for reads at least as large as it.
+        if self.pos >= self.cap && out.len() >= self.buf.len() {
+            return self.reader.read(out);
+        }
+        let amt = {
+            let available = self.fill_buf()?;
+            let amt = cmp::min(available.len(), out.len());
+            out[..amt].copy_from_slice(&available[..amt]);
+            amt
+        };
+        self.consume(amt);
+        Ok(amt)
+    }
+}
+
+impl<R: Read> BufRead for EmbeddedBufReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8], R::Error> {
+        if self.pos >= self.cap {
+            self.cap = self.reader.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for EmbeddedBufReader<R> {
+    type Config = BufConfig;
+
+    fn wrap_with(reader: R, config: BufConfig) -> Self {
+        EmbeddedBufReader {
+            reader,
+            buf: vec![0; config.capacity].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// A buffered writer over an `embedded_io::Write`, which works without std.
+///
+/// Call `flush` or unwrap the writer before dropping it: the buffer is not written out on drop,
+/// where an embedded_io error could not be reported.
+pub struct EmbeddedBufWriter<W> {
+    writer: W,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+/// The error of an EmbeddedBufWriter.
+#[derive(Debug)]
+pub enum EmbeddedWriteError<E> {
+    /// The inner Write failed.
+    Write(E),
+    /// The inner Write accepted none of the buffered data.
+    WriteZero,
+}
+
+impl<E: embedded_io::Error> embedded_io::Error for EmbeddedWriteError<E> {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            EmbeddedWriteError::Write(ref error)    => error.kind(),
+            EmbeddedWriteError::WriteZero           => ErrorKind::WriteZero,
+        }
+    }
+}
+
+impl<W: Write> EmbeddedBufWriter<W> {
+    fn flush_buf(&mut self) -> Result<(), EmbeddedWriteError<W::Error>> {
+        let mut written = 0;
+        let mut result = Ok(());
+        while written < self.buf.len() {
+            match self.writer.write(&self.buf[written..]) {
+                Ok(0)       => {
+                    result = Err(EmbeddedWriteError::WriteZero);
+                    break;
+                }
+                Ok(n)       => written += n,
+                Err(error)  => {
+                    result = Err(EmbeddedWriteError::Write(error));
+                    break;
+                }
+            }
+        }
+        self.buf.drain(..written);
+        result
+    }
+}
+
+impl<W: Write> ErrorType for EmbeddedBufWriter<W> {
+    type Error = EmbeddedWriteError<W::Error>;
+}
+
+impl<W: Write> Write for EmbeddedBufWriter<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error> {
+        if self.buf.len() + data.len() > self.capacity {
+            self.flush_buf()?;
+        }
+        if data.len() >= self.capacity {
+            self.writer.write(data).map_err(EmbeddedWriteError::Write)
+        } else {
+            self.buf.extend_from_slice(data);
+            Ok(data.len())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_buf()?;
+        self.writer.flush().map_err(EmbeddedWriteError::Write)
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for EmbeddedBufWriter<W> {
+    type Config = BufConfig;
+
+    fn wrap_with(writer: W, config: BufConfig) -> Self {
+        EmbeddedBufWriter {
+            writer,
+            buf: Vec::with_capacity(config.capacity),
+            capacity: config.capacity,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap EmbeddedBufWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        match self.flush_buf() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.buf.len();
+                Err(UnwrapError::new(self, io_error(embedded_io::Error::kind(&error)), remaining))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn io_error(kind: ErrorKind) -> IoError {
+    IoError::from(::std::io::ErrorKind::from(kind))
+}
+
+#[cfg(not(feature = "std"))]
+fn io_error(kind: ErrorKind) -> IoError {
+    kind
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use {BufConfig, WriteAdapter};
+    use super::embedded_io::{ErrorType, Write};
+    use super::{EmbeddedBufWriter, EmbeddedWriteError};
+
+    // A Write which accepts nothing the first time it is written to.
+    struct Stalling {
+        stalled: bool,
+        data: Vec<u8>,
+    }
+
+    impl ErrorType for Stalling {
+        type Error = super::embedded_io::ErrorKind;
+    }
+
+    impl Write for Stalling {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            if !self.stalled {
+                self.stalled = true;
+                return Ok(0);
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_zero_keeps_order() {
+        let stalling = Stalling { stalled: false, data: Vec::new() };
+        let mut writer = EmbeddedBufWriter::wrap_with(stalling, BufConfig { capacity: 4 });
+        assert_eq!(writer.write(b"abc").unwrap(), 3);
+        match writer.write(b"DEFGH") {
+            Err(EmbeddedWriteError::WriteZero)  => {}
+            result                              => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(writer.write(b"DEFGH").unwrap(), 5);
+        assert_eq!(writer.into_inner().data, b"abcDEFGH");
+    }
+
+    #[test]
+    fn flush_fails_on_write_zero() {
+        let stalling = Stalling { stalled: false, data: Vec::new() };
+        let mut writer = EmbeddedBufWriter::wrap_with(stalling, BufConfig { capacity: 4 });
+        writer.write_all(b"ab").unwrap();
+        assert!(writer.flush().is_err());
+        writer.flush().unwrap();
+        assert_eq!(writer.into_inner().data, b"ab");
+    }
+}
@@ -0,0 +1,483 @@
+use std::io::{self, Read, Write};
+use std::str;
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+extern crate encoding_rs;
+
+use self::encoding_rs::{CoderResult, Decoder, DecoderResult, Encoder, EncoderResult, Encoding};
+
+/// What transcoding adapters do with input which cannot be transcoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscodeErrors {
+    /// Replace it: malformed input is decoded as U+FFFD, and characters the target encoding
+    /// cannot represent are encoded as HTML numeric character references, as WHATWG specifies.
+    Replace,
+    /// Fail with `ErrorKind::InvalidData`.
+    Fail,
+}
+
+/// The configuration for TranscodeReader and TranscodeWriter. The default is UTF-8, sniffing
+/// the BOM and replacing errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TranscodeConfig {
+    /// The encoding to decode from or encode into.
+    pub encoding: &'static Encoding,
+    /// Let a UTF-8 or UTF-16 BOM at the start of the input override the encoding, and remove
+    /// it. Writers ignore this.
+    pub sniff_bom: bool,
+    /// What to do with input which cannot be transcoded.
+    pub errors: TranscodeErrors,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> TranscodeConfig {
+        TranscodeConfig {
+            encoding: encoding_rs::UTF_8,
+            sniff_bom: true,
+            errors: TranscodeErrors::Replace,
+        }
+    }
+}
+
+/// A ReadAdapter which decodes text in any WHATWG encoding read from its inner Read into UTF-8.
+///
+/// The input is read ahead in chunks, so unwrapping fails if the reader holds input or decoded
+/// text which has not been read from it yet. It also fails if the input read so far ends in an
+/// unfinished multibyte sequence; that sequence is dropped, and the reader returned in the error
+/// is at the end of its input.
+pub struct TranscodeReader<R> {
+    reader: R,
+    decoder: Decoder,
+    errors: TranscodeErrors,
+    input: Box<[u8]>,
+    in_pos: usize,
+    in_len: usize,
+    eof: bool,
+    out: Box<[u8]>,
+    out_pos: usize,
+    out_len: usize,
+    malformed: Option<u8>,
+    finished: bool,
+}
+
+impl<R: Read> TranscodeReader<R> {
+    fn decode(&mut self) {
+        let src = &self.input[self.in_pos..self.in_len];
+        let (finished, read, written) = match self.errors {
+            TranscodeErrors::Replace    => {
+                let (result, read, written, _) =
+                    self.decoder.decode_to_utf8(src, &mut self.out, self.eof);
+                (result == CoderResult::InputEmpty, read, written)
+            }
+            TranscodeErrors::Fail       => {
+                let (result, read, written) =
+                    self.decoder.decode_to_utf8_without_replacement(src, &mut self.out, self.eof);
+                if let DecoderResult::Malformed(bad, _) = result {
+                    self.malformed = Some(bad);
+                }
+                (result == DecoderResult::InputEmpty, read, written)
+            }
+        };
+        self.in_pos += read;
+        self.out_pos = 0;
+        self.out_len = written;
+        self.finished = self.eof && finished;
+    }
+}
+
+impl<R: Read> Read for TranscodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.out_pos < self.out_len {
+                let amt = (&self.out[self.out_pos..self.out_len]).read(buf)?;
+                self.out_pos += amt;
+                return Ok(amt);
+            }
+            // Text decoded before malformed input is returned before the error.
+            if let Some(bad) = self.malformed.take() {
+                let error = format!("malformed sequence of {} bytes in the input", bad);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            if self.in_pos == self.in_len && !self.eof {
+                self.in_len = self.reader.read(&mut self.input)?;
+                self.in_pos = 0;
+                self.eof = self.in_len == 0;
+            }
+            self.decode();
+        }
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for TranscodeReader<R> {
+    type Config = TranscodeConfig;
+
+    fn wrap_with(reader: R, config: TranscodeConfig) -> Self {
+        let decoder = match config.sniff_bom {
+            true    => config.encoding.new_decoder(),
+            false   => config.encoding.new_decoder_without_bom_handling(),
+        };
+        TranscodeReader {
+            reader,
+            decoder,
+            errors: config.errors,
+            input: vec![0; 8 * 1024].into_boxed_slice(),
+            in_pos: 0,
+            in_len: 0,
+            eof: false,
+            out: vec![0; 8 * 1024].into_boxed_slice(),
+            out_pos: 0,
+            out_len: 0,
+            malformed: None,
+            finished: false,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap TranscodeReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.in_len - self.in_pos + self.out_len - self.out_pos;
+        if remaining > 0 {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            return Err(UnwrapError::new(self, io::Error::other(error), remaining));
+        }
+
+        if !self.finished {
+            // The decoder does not expose what it holds, but finishing it reveals an unfinished
+            // sequence as malformed.
+            let (result, _, written) =
+                self.decoder.decode_to_utf8_without_replacement(&[], &mut self.out, true);
+            self.out_pos = 0;
+            self.out_len = written;
+            self.finished = true;
+            if let DecoderResult::Malformed(bad, _) = result {
+                let error = format!("input ended in an unfinished sequence of {} bytes", bad);
+                let error = io::Error::new(io::ErrorKind::InvalidData, error);
+                return Err(UnwrapError::new(self, error, bad as usize));
+            }
+            if written > 0 {
+                let error = format!("{} bytes were read ahead of the reader", written);
+                return Err(UnwrapError::new(self, io::Error::other(error), written));
+            }
+        }
+
+        Ok(self.reader)
+    }
+}
+
+/// A WriteAdapter which encodes the UTF-8 text written to it into the configured encoding.
+///
+/// Encoded text is held until the next write or flush. Writes can split characters; unwrapping
+/// fails if the text written ends in an unfinished UTF-8 sequence.
+///
+/// When errors fail, a write encodes the text up to the first character the encoding cannot
+/// represent, and the next write fails on that character without encoding anything.
+pub struct TranscodeWriter<W> {
+    writer: W,
+    encoder: Encoder,
+    // WHATWG encoders write UTF-8 in place of UTF-16, so UTF-16 is encoded here instead.
+    utf16: Option<fn(u16) -> [u8; 2]>,
+    errors: TranscodeErrors,
+    pending: [u8; 4],
+    pending_len: usize,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> TranscodeWriter<W> {
+    // Encode text, returning how much of it was encoded. That is all of it, unless errors fail
+    // and it contains a character the encoding cannot represent, which is returned too.
+    fn encode(&mut self, mut text: &str, last: bool) -> (usize, Option<char>) {
+        if let Some(to_bytes) = self.utf16 {
+            for unit in text.encode_utf16() {
+                self.out.extend_from_slice(&to_bytes(unit));
+            }
+            return (text.len(), None);
+        }
+
+        let mut encoded = 0;
+        loop {
+            let needed = self.encoder.max_buffer_length_from_utf8_without_replacement(text.len());
+            self.out.reserve(needed.unwrap_or(text.len()) + 16);
+            let (full, read) = match self.errors {
+                TranscodeErrors::Replace    => {
+                    let (result, read, _) =
+                        self.encoder.encode_from_utf8_to_vec(text, &mut self.out, last);
+                    (result == CoderResult::OutputFull, read)
+                }
+                TranscodeErrors::Fail       => {
+                    let (result, read) = self.encoder
+                        .encode_from_utf8_to_vec_without_replacement(text, &mut self.out, last);
+                    if let EncoderResult::Unmappable(c) = result {
+                        return (encoded + read - c.len_utf8(), Some(c));
+                    }
+                    (result == EncoderResult::OutputFull, read)
+                }
+            };
+            text = &text[read..];
+            encoded += read;
+            if !full {
+                return (encoded, None);
+            }
+        }
+    }
+
+    fn unmappable(&self, c: char) -> io::Error {
+        let error = format!("{:?} cannot be encoded in {}", c, self.encoder.encoding().name());
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for TranscodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_out()?;
+
+        // Complete a character split by the last write first.
+        let mut consumed = 0;
+        let pending_len = self.pending_len;
+        while self.pending_len > 0 {
+            if consumed == buf.len() {
+                return Ok(consumed);
+            }
+            self.pending[self.pending_len] = buf[consumed];
+            self.pending_len += 1;
+            consumed += 1;
+            let pending = self.pending;
+            match str::from_utf8(&pending[..self.pending_len]) {
+                Ok(text)                                    => {
+                    if let (_, Some(c)) = self.encode(text, false) {
+                        self.pending_len = pending_len;
+                        return Err(self.unmappable(c));
+                    }
+                    self.pending_len = 0;
+                }
+                Err(ref error) if error.error_len().is_none()   => {}
+                Err(_)                                      => {
+                    self.pending_len = 0;
+                    return Err(invalid_utf8());
+                }
+            }
+        }
+
+        let buf = &buf[consumed..];
+        let (valid, rest) = match str::from_utf8(buf) {
+            Ok(text)    => (text, None),
+            Err(error)  => {
+                let (valid, rest) = buf.split_at(error.valid_up_to());
+                if valid.is_empty() && consumed == 0 && error.error_len().is_some() {
+                    return Err(invalid_utf8());
+                }
+                (str::from_utf8(valid).unwrap(), Some((rest, error.error_len())))
+            }
+        };
+        match self.encode(valid, false) {
+            (0, Some(c)) if consumed == 0   => return Err(self.unmappable(c)),
+            (encoded, Some(_))              => return Ok(consumed + encoded),
+            (_, None)                       => {}
+        }
+        match rest {
+            None                => Ok(consumed + buf.len()),
+            Some((rest, None))  => {
+                self.pending[..rest.len()].copy_from_slice(rest);
+                self.pending_len = rest.len();
+                Ok(consumed + buf.len())
+            }
+            // The invalid sequence is reported by the next write.
+            Some((_, Some(_)))  => Ok(consumed + valid.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for TranscodeWriter<W> {
+    type Config = TranscodeConfig;
+
+    fn wrap_with(writer: W, config: TranscodeConfig) -> Self {
+        let utf16: Option<fn(u16) -> [u8; 2]> = match config.encoding {
+            encoding if encoding == encoding_rs::UTF_16LE   => Some(u16::to_le_bytes),
+            encoding if encoding == encoding_rs::UTF_16BE   => Some(u16::to_be_bytes),
+            _                                               => None,
+        };
+        TranscodeWriter {
+            writer,
+            encoder: config.encoding.new_encoder(),
+            utf16,
+            errors: config.errors,
+            pending: [0; 4],
+            pending_len: 0,
+            out: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap TranscodeWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        if self.pending_len > 0 {
+            // Write the text before the unfinished sequence, so only the sequence remains.
+            if let Err(error) = self.flush() {
+                let remaining = self.pending_len + self.out.len();
+                return Err(UnwrapError::new(self, error, remaining));
+            }
+            let error = format!("text ended in an unfinished UTF-8 sequence of {} bytes",
+                                self.pending_len);
+            let error = io::Error::new(io::ErrorKind::InvalidData, error);
+            let remaining = self.pending_len;
+            return Err(UnwrapError::new(self, error, remaining));
+        }
+
+        // Some encodings, such as ISO-2022-JP, end by switching back to ASCII.
+        if !self.finished {
+            self.encode("", true);
+            self.finished = true;
+        }
+
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::encoding_rs::{UTF_16BE, UTF_16LE, WINDOWS_1252};
+    use super::{TranscodeConfig, TranscodeErrors, TranscodeReader, TranscodeWriter};
+
+    fn decode(input: &[u8], config: TranscodeConfig) -> String {
+        let mut text = String::new();
+        TranscodeReader::wrap_with(input, config).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn bom_sniffed() {
+        let config = TranscodeConfig { encoding: WINDOWS_1252, ..TranscodeConfig::default() };
+        assert_eq!(decode(b"\xff\xfea\x00\xe9\x00", config), "a\u{e9}");
+        assert_eq!(decode(b"\xfe\xff\x00a\x00\xe9", config), "a\u{e9}");
+        assert_eq!(decode(b"\xef\xbb\xbfa\xc3\xa9", config), "a\u{e9}");
+        // Without a BOM, the configured encoding is used.
+        assert_eq!(decode(b"a\xe9", config), "a\u{e9}");
+
+        let config = TranscodeConfig { sniff_bom: false, ..TranscodeConfig::default() };
+        assert_eq!(decode(b"\xef\xbb\xbfa", config), "\u{feff}a");
+    }
+
+    #[test]
+    fn malformed_input() {
+        let input = b"ab\xffcd";
+        assert_eq!(decode(input, TranscodeConfig::default()), "ab\u{fffd}cd");
+
+        let config = TranscodeConfig { errors: TranscodeErrors::Fail, ..Default::default() };
+        let mut reader = TranscodeReader::wrap_with(&input[..], config);
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        // Decoding carries on after the malformed sequence.
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"cd");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn unfinished_sequence_at_unwrap() {
+        // The input ends halfway through a euro sign, without the reader reaching its end.
+        let mut reader = TranscodeReader::wrap(&b"a\xe2\x82"[..]);
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        let error = match reader.try_into_inner() {
+            Ok(_)       => panic!("unwrapped with an unfinished sequence"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.error().kind(), ErrorKind::InvalidData);
+        assert_eq!(error.remaining(), 2);
+
+        // The sequence has been dropped, and the reader is at the end of its input.
+        let mut reader = error.into_inner();
+        assert!(reader.get_ref().is_empty());
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.try_into_inner().is_ok());
+    }
+
+    #[test]
+    fn utf16_encoded() {
+        let config = TranscodeConfig { encoding: UTF_16LE, ..TranscodeConfig::default() };
+        let mut writer = TranscodeWriter::wrap_with(Vec::new(), config);
+        writer.write_all("a\u{e9}\u{1f600}".as_bytes()).unwrap();
+        assert_eq!(writer.into_inner(), [0x61, 0, 0xe9, 0, 0x3d, 0xd8, 0x00, 0xde]);
+
+        let config = TranscodeConfig { encoding: UTF_16BE, ..TranscodeConfig::default() };
+        let mut writer = TranscodeWriter::wrap_with(Vec::new(), config);
+        writer.write_all("a\u{e9}".as_bytes()).unwrap();
+        assert_eq!(writer.into_inner(), [0, 0x61, 0, 0xe9]);
+    }
+
+    #[test]
+    fn fail_stops_before_unmappable() {
+        let config = TranscodeConfig {
+            encoding: WINDOWS_1252,
+            errors: TranscodeErrors::Fail,
+            ..TranscodeConfig::default()
+        };
+        let mut writer = TranscodeWriter::wrap_with(Vec::new(), config);
+        let text = "ab\u{3b1}c".as_bytes();
+        assert_eq!(writer.write(text).unwrap(), 2);
+        assert_eq!(writer.write(&text[2..]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(writer.write(&text[2..]).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // A character split across writes fails without being taken.
+        assert_eq!(writer.write(&text[2..3]).unwrap(), 1);
+        assert_eq!(writer.write(&text[3..]).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(writer.write(&text[3..]).unwrap_err().kind(), ErrorKind::InvalidData);
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"ab");
+    }
+}
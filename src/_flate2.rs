@@ -0,0 +1,136 @@
+use std::io::{Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+
+extern crate flate2;
+
+use self::flate2::Compression;
+use self::flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use self::flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
+// The encoders write their trailer when they are finished. flate2 does not report how much
+// compressed output is still pending when that fails, so the remaining count is always 0.
+macro_rules! encoder {
+    ($encoder:ident) => {
+        impl<W: Write> WriteAdapter<W> for $encoder<W> {
+            type Config = Compression;
+
+            fn wrap_with(writer: W, level: Compression) -> Self {
+                $encoder::new(writer, level)
+            }
+
+            fn get_ref(&self) -> &W {
+                self.get_ref()
+            }
+
+            fn get_mut(&mut self) -> &mut W {
+                self.get_mut()
+            }
+
+            fn into_inner(self) -> W {
+                match WriteAdapter::try_into_inner(self) {
+                    Ok(writer)  => writer,
+                    Err(error)  => {
+                        panic!(concat!("Failed to unwrap ", stringify!($encoder), ": {:?}"),
+                               error.error())
+                    }
+                }
+            }
+
+            fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+                match self.try_finish() {
+                    // Once try_finish has succeeded, finish has nothing left to write.
+                    Ok(())      => Ok(self.finish().expect("finished encoder failed to finish")),
+                    Err(error)  => Err(UnwrapError::new(self, error, 0)),
+                }
+            }
+        }
+    }
+}
+
+// The decoders read ahead of the compressed stream through an internal buffer, which is dropped
+// when they are unwrapped.
+macro_rules! decoder {
+    ($decoder:ident) => {
+        impl<R: Read> ReadAdapter<R> for $decoder<R> {
+            type Config = ();
+
+            fn wrap_with(reader: R, _: ()) -> Self {
+                $decoder::new(reader)
+            }
+
+            fn get_ref(&self) -> &R {
+                self.get_ref()
+            }
+
+            fn get_mut(&mut self) -> &mut R {
+                self.get_mut()
+            }
+
+            fn into_inner(self) -> R {
+                self.into_inner()
+            }
+        }
+    }
+}
+
+encoder!(GzEncoder);
+encoder!(ZlibEncoder);
+encoder!(DeflateEncoder);
+
+decoder!(GzDecoder);
+decoder!(ZlibDecoder);
+decoder!(DeflateDecoder);
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::flate2::Compression;
+    use super::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+
+    fn round_trip<E, D>(level: Compression)
+        where E: WriteAdapter<Vec<u8>, Config = Compression> + Write,
+              D: ReadAdapter<Cursor<Vec<u8>>, Config = ()> + Read
+    {
+        let data: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let mut encoder = E::wrap_with(Vec::new(), level);
+        encoder.write_all(&data).unwrap();
+        let encoded = encoder.into_inner();
+        assert!(encoded.len() < data.len());
+
+        let mut decoded = Vec::new();
+        D::wrap(Cursor::new(encoded)).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        round_trip::<GzEncoder<_>, GzDecoder<_>>(Compression::best());
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        round_trip::<ZlibEncoder<_>, ZlibDecoder<_>>(Compression::default());
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        round_trip::<DeflateEncoder<_>, DeflateDecoder<_>>(Compression::fast());
+    }
+
+    #[test]
+    fn unwrap_fails_when_trailer_cannot_be_written() {
+        // Room for the gzip header, but not for the compressed data and trailer.
+        let mut buf = [0; 12];
+        let mut encoder = GzEncoder::wrap(&mut buf[..]);
+        encoder.write_all(b"data").unwrap();
+        let error = match encoder.try_into_inner() {
+            Ok(_)       => panic!("unwrapped into a full buffer"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.error().kind(), ErrorKind::WriteZero);
+        assert_eq!(error.remaining(), 0);
+        assert!(error.into_inner().get_ref().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{Read, Write};
+
+use {ReadAdapter, WriteAdapter};
+
+extern crate rmp_serde as rmp;
+
+use self::rmp::decode::ReadReader;
+use self::rmp::{Deserializer, Serializer};
+
+impl<W: Write> WriteAdapter<W> for Serializer<W> {
+    type Config = ();
+
+    fn wrap_with(writer: W, _: ()) -> Self {
+        Serializer::new(writer)
+    }
+
+    fn get_ref(&self) -> &W {
+        self.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        self.get_mut()
+    }
+
+    fn into_inner(self) -> W {
+        self.into_inner()
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for Deserializer<ReadReader<R>> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        Deserializer::new(reader)
+    }
+
+    fn get_ref(&self) -> &R {
+        self.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        self.get_mut()
+    }
+
+    fn into_inner(self) -> R {
+        self.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde1 as serde;
+
+    use {ReadAdapter, WriteAdapter};
+    use self::serde::{Deserialize, Serialize};
+    use super::{Deserializer, Serializer};
+
+    #[test]
+    fn round_trip() {
+        let value = (7u32, "seven".to_string(), vec![1u8, 2, 3], Some(true));
+        let mut ser = Serializer::wrap(Vec::new());
+        value.serialize(&mut ser).unwrap();
+        let mut encoded = match ser.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap Serializer: {:?}", error.error()),
+        };
+        encoded.extend_from_slice(b"rest");
+
+        let mut de = Deserializer::wrap(&encoded[..]);
+        assert_eq!(Deserialize::deserialize(&mut de).ok(), Some(value));
+        // Nothing past the value has been read.
+        match de.try_into_inner() {
+            Ok(reader)  => assert_eq!(reader, b"rest"),
+            Err(error)  => panic!("Failed to unwrap Deserializer: {:?}", error.error()),
+        }
+
+        // A truncated value fails to deserialize, and the deserializer still unwraps.
+        let mut de = Deserializer::wrap(&encoded[..4]);
+        let value: Result<(u32, String, Vec<u8>, Option<bool>), _> =
+            Deserialize::deserialize(&mut de);
+        assert!(value.is_err());
+        assert!(de.try_into_inner().is_ok());
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::{Read, Write};
+
+use {ReadAdapter, WriteAdapter};
+
+extern crate serde1 as serde;
+extern crate serde_cbor as cbor;
+
+use self::cbor::de::IoRead;
+use self::cbor::ser::IoWrite;
+
+/// A CBOR serializer over a Write type, which can be unwrapped to get the Write back.
+///
+/// `serde_cbor::Serializer` does not give back a `std::io::Write` it was constructed over, so
+/// this serializes each value with a serializer borrowing the Write instead.
+pub struct CborSerializer<W> {
+    writer: W,
+}
+
+impl<W: Write> CborSerializer<W> {
+    /// Serialize a value as CBOR to the writer.
+    pub fn serialize<T: serde::Serialize>(&mut self, value: &T) -> cbor::Result<()> {
+        let mut ser = cbor::Serializer::new(IoWrite::new(&mut self.writer));
+        value.serialize(&mut ser)
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for CborSerializer<W> {
+    type Config = ();
+
+    fn wrap_with(writer: W, _: ()) -> Self {
+        CborSerializer { writer }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// A CBOR deserializer over a Read type, which can be unwrapped to get the Read back.
+///
+/// CBOR values are self delimiting, so nothing is read past the end of a value.
+pub struct CborDeserializer<R> {
+    reader: R,
+}
+
+impl<R: Read> CborDeserializer<R> {
+    /// Deserialize the next CBOR value from the reader.
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&mut self) -> cbor::Result<T> {
+        let mut de = cbor::Deserializer::new(IoRead::new(&mut self.reader));
+        T::deserialize(&mut de)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for CborDeserializer<R> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        CborDeserializer { reader }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {ReadAdapter, WriteAdapter};
+    use super::{CborDeserializer, CborSerializer};
+
+    #[test]
+    fn round_trip() {
+        let value = (7u32, "seven".to_string(), vec![1u8, 2, 3], Some(true));
+        let mut ser = CborSerializer::wrap(Vec::new());
+        ser.serialize(&value).unwrap();
+        let mut encoded = match ser.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap CborSerializer: {:?}", error.error()),
+        };
+        encoded.extend_from_slice(b"rest");
+
+        let mut de = CborDeserializer::wrap(&encoded[..]);
+        assert_eq!(de.deserialize().ok(), Some(value));
+        // Nothing past the value has been read.
+        match de.try_into_inner() {
+            Ok(reader)  => assert_eq!(reader, b"rest"),
+            Err(error)  => panic!("Failed to unwrap CborDeserializer: {:?}", error.error()),
+        }
+
+        // A truncated value fails to deserialize, and the deserializer still unwraps.
+        let mut de = CborDeserializer::wrap(&encoded[..4]);
+        assert!(de.deserialize::<(u32, String, Vec<u8>, Option<bool>)>().is_err());
+        assert!(de.try_into_inner().is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use {BufConfig, UnwrapError};
+
+extern crate tokio;
+
+use self::tokio::io::{AsyncRead, AsyncWrite};
+
+/// Any type which can be adapted over an AsyncRead type.
+pub trait AsyncReadAdapter<R: AsyncRead> {
+    /// The configuration this adapter can be wrapped with. Adapters with nothing to configure
+    /// should use `()`.
+    type Config: Default;
+
+    /// Wrap an AsyncRead type in this adapter, using the default configuration.
+    fn wrap(reader: R) -> Self where Self: Sized {
+        Self::wrap_with(reader, Self::Config::default())
+    }
+
+    /// Wrap an AsyncRead type in this adapter, using the given configuration.
+    fn wrap_with(reader: R, config: Self::Config) -> Self;
+
+    /// Get a reference to the inner AsyncRead.
+    fn get_ref(&self) -> &R;
+
+    /// Get a mutable reference to the inner AsyncRead. Reading from it directly may corrupt the
+    /// state of this adapter.
+    fn get_mut(&mut self) -> &mut R;
+
+    /// Unwrap this type to get its inner AsyncRead. If this action could fail, this call should
+    /// panic on fail.
+    fn into_inner(self) -> R;
+
+    /// Try to unwrap this type. If this action could fail, it should yield an UnwrapError if it
+    /// fails. This method is implemented by default on the assumption that into_inner cannot
+    /// fail; if it can, this method needs to be correctly implemented.
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> where Self: Sized {
+        Ok(self.into_inner())
+    }
+}
+
+/// Any type which can be adapted over an AsyncWrite type.
+///
+/// Because an adapter cannot write to its inner AsyncWrite without being polled, unwrapping is
+/// split in two: `poll_flush_adapter` writes out any data the adapter holds, and `into_inner`
+/// then takes the inner AsyncWrite. `try_into_inner` returns a future which does both.
+pub trait AsyncWriteAdapter<W: AsyncWrite> {
+    /// The configuration this adapter can be wrapped with. Adapters with nothing to configure
+    /// should use `()`.
+    type Config: Default;
+
+    /// Wrap an AsyncWrite type in this adapter, using the default configuration.
+    fn wrap(writer: W) -> Self where Self: Sized {
+        Self::wrap_with(writer, Self::Config::default())
+    }
+
+    /// Wrap an AsyncWrite type in this adapter, using the given configuration.
+    fn wrap_with(writer: W, config: Self::Config) -> Self;
+
+    /// Get a reference to the inner AsyncWrite.
+    fn get_ref(&self) -> &W;
+
+    /// Get a mutable reference to the inner AsyncWrite. Writing to it directly may corrupt the
+    /// state of this adapter.
+    fn get_mut(&mut self) -> &mut W;
+
+    /// Write out any data this adapter holds to the inner AsyncWrite, so that it can be unwrapped
+    /// without losing data.
+    fn poll_flush_adapter(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// The number of bytes this adapter holds which have not been written to the inner
+    /// AsyncWrite. This is implemented by default to return 0.
+    fn unflushed(&self) -> usize {
+        0
+    }
+
+    /// Unwrap this type to get its inner AsyncWrite. If this action could fail, because data has
+    /// not been written out by `poll_flush_adapter`, this call should panic on fail.
+    fn into_inner(self) -> W;
+
+    /// Write out any data this adapter holds and unwrap it. The returned future yields an
+    /// UnwrapError holding the adapter if writing fails.
+    fn try_into_inner(self) -> TryIntoInner<Self, W> where Self: Sized + Unpin {
+        TryIntoInner {
+            adapter: Some(self),
+            _writer: PhantomData,
+        }
+    }
+}
+
+/// The future returned by `AsyncWriteAdapter::try_into_inner`.
+pub struct TryIntoInner<A, W> {
+    adapter: Option<A>,
+    _writer: PhantomData<fn() -> W>,
+}
+
+impl<W: AsyncWrite, A: AsyncWriteAdapter<W> + Unpin> Future for TryIntoInner<A, W> {
+    type Output = Result<W, UnwrapError<A>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = {
+            let adapter = self.adapter.as_mut().expect("TryIntoInner polled after completion");
+            match Pin::new(adapter).poll_flush_adapter(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending       => return Poll::Pending,
+            }
+        };
+        let adapter = self.adapter.take().unwrap();
+        Poll::Ready(match result {
+            Ok(())      => Ok(adapter.into_inner()),
+            Err(error)  => {
+                let remaining = adapter.unflushed();
+                Err(UnwrapError::new(adapter, error, remaining))
+            }
+        })
+    }
+}
+
+impl<R: AsyncRead> AsyncReadAdapter<R> for tokio::io::BufReader<R> {
+    type Config = BufConfig;
+
+    fn wrap_with(reader: R, config: BufConfig) -> Self {
+        tokio::io::BufReader::with_capacity(config.capacity, reader)
+    }
+
+    fn get_ref(&self) -> &R {
+        self.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        self.get_mut()
+    }
+
+    fn into_inner(self) -> R {
+        self.into_inner()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWriteAdapter<W> for tokio::io::BufWriter<W> {
+    type Config = BufConfig;
+
+    fn wrap_with(writer: W, config: BufConfig) -> Self {
+        tokio::io::BufWriter::with_capacity(config.capacity, writer)
+    }
+
+    fn get_ref(&self) -> &W {
+        self.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        self.get_mut()
+    }
+
+    fn poll_flush_adapter(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn unflushed(&self) -> usize {
+        self.buffer().len()
+    }
+
+    fn into_inner(self) -> W {
+        match self.buffer().len() {
+            0           => self.into_inner(),
+            remaining   => {
+                panic!("Failed to unwrap BufWriter: {} bytes were not flushed", remaining)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use super::tokio::io::{AsyncWrite, BufWriter};
+    use super::AsyncWriteAdapter;
+
+    fn buffered() -> BufWriter<Vec<u8>> {
+        let mut writer: BufWriter<Vec<u8>> = AsyncWriteAdapter::wrap(Vec::new());
+        let mut cx = Context::from_waker(Waker::noop());
+        match Pin::new(&mut writer).poll_write(&mut cx, b"data") {
+            Poll::Ready(result) => assert_eq!(result.unwrap(), 4),
+            Poll::Pending       => panic!("writing to a Vec was pending"),
+        }
+        writer
+    }
+
+    #[test]
+    #[should_panic(expected = "4 bytes were not flushed")]
+    fn into_inner_with_unflushed_data() {
+        AsyncWriteAdapter::into_inner(buffered());
+    }
+
+    #[test]
+    fn try_into_inner_flushes() {
+        let mut future = AsyncWriteAdapter::try_into_inner(buffered());
+        let mut cx = Context::from_waker(Waker::noop());
+        match Pin::new(&mut future).poll(&mut cx) {
+            Poll::Ready(result) => assert_eq!(result.ok().unwrap(), b"data"),
+            Poll::Pending       => panic!("flushing to a Vec was pending"),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{self, BufReader, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+
+extern crate zstd;
+
+use self::zstd::stream::read::Decoder;
+use self::zstd::stream::write::Encoder;
+
+/// The configuration for zstd encoders and decoders.
+///
+/// A stream must be decoded with the same dictionary it was encoded with, and a decoder needs
+/// `long` set to at least the encoder's window log, so the same configuration can be used for
+/// both ends. Decoders ignore the level.
+///
+/// # Panics
+///
+/// `wrap_with` panics if zstd rejects the configuration, such as a level or window log outside
+/// the range zstd supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZstdConfig {
+    /// The compression level.
+    pub level: i32,
+    /// The dictionary to compress and decompress with, if any.
+    pub dictionary: Option<Vec<u8>>,
+    /// Enable long distance matching with this window log, the base 2 logarithm of the
+    /// maximum back-reference distance. Decoders allow windows up to this size.
+    pub long: Option<u32>,
+}
+
+impl Default for ZstdConfig {
+    fn default() -> ZstdConfig {
+        ZstdConfig {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            dictionary: None,
+            long: None,
+        }
+    }
+}
+
+// Creating a zstd context only fails on an invalid configuration, which wrap_with has no way to
+// report, so the adapters panic instead.
+fn encoder<W: Write>(writer: W, config: ZstdConfig) -> io::Result<Encoder<'static, W>> {
+    let mut encoder = match config.dictionary {
+        Some(ref dictionary)    => Encoder::with_dictionary(writer, config.level, dictionary)?,
+        None                    => Encoder::new(writer, config.level)?,
+    };
+    if let Some(window_log) = config.long {
+        encoder.long_distance_matching(true)?;
+        encoder.window_log(window_log)?;
+    }
+    Ok(encoder)
+}
+
+fn decoder<R: Read>(reader: R, config: ZstdConfig)
+    -> io::Result<Decoder<'static, BufReader<R>>>
+{
+    let reader = BufReader::with_capacity(Decoder::<BufReader<R>>::recommended_output_size(),
+                                          reader);
+    let mut decoder = match config.dictionary {
+        Some(ref dictionary)    => Decoder::with_dictionary(reader, dictionary)?,
+        None                    => Decoder::with_buffer(reader)?,
+    };
+    if let Some(window_log) = config.long {
+        decoder.window_log_max(window_log)?;
+    }
+    Ok(decoder)
+}
+
+impl<W: Write> WriteAdapter<W> for Encoder<'static, W> {
+    type Config = ZstdConfig;
+
+    fn wrap_with(writer: W, config: ZstdConfig) -> Self {
+        match encoder(writer, config) {
+            Ok(encoder) => encoder,
+            Err(error)  => panic!("Failed to wrap zstd Encoder: {:?}", error),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        self.get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        self.get_mut()
+    }
+
+    fn into_inner(self) -> W {
+        match WriteAdapter::try_into_inner(self) {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap zstd Encoder: {:?}", error.error()),
+        }
+    }
+
+    // zstd does not report how much compressed output is still pending when finishing the frame
+    // fails, so the remaining count is always 0.
+    fn try_into_inner(self) -> Result<W, UnwrapError<Self>> {
+        self.try_finish().map_err(|(encoder, error)| UnwrapError::new(encoder, error, 0))
+    }
+}
+
+// The decoder reads ahead of the compressed stream through a BufReader, which is dropped when it
+// is unwrapped.
+impl<R: Read> ReadAdapter<R> for Decoder<'static, BufReader<R>> {
+    type Config = ZstdConfig;
+
+    fn wrap_with(reader: R, config: ZstdConfig) -> Self {
+        match decoder(reader, config) {
+            Ok(decoder) => decoder,
+            Err(error)  => panic!("Failed to wrap zstd Decoder: {:?}", error),
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        self.get_ref().get_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        self.get_mut().get_mut()
+    }
+
+    fn into_inner(self) -> R {
+        self.finish().into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{Decoder, Encoder, ZstdConfig};
+
+    fn round_trip(config: ZstdConfig) {
+        let data: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let mut encoder = Encoder::wrap_with(Vec::new(), config.clone());
+        encoder.write_all(&data).unwrap();
+        let encoded = encoder.into_inner();
+        assert!(encoded.len() < data.len());
+
+        let mut decoded = Vec::new();
+        Decoder::wrap_with(&encoded[..], config).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn default_round_trip() {
+        round_trip(ZstdConfig::default());
+    }
+
+    #[test]
+    fn dictionary_round_trip() {
+        let dictionary = (0..251).collect();
+        round_trip(ZstdConfig { dictionary: Some(dictionary), ..ZstdConfig::default() });
+    }
+
+    #[test]
+    fn long_round_trip() {
+        round_trip(ZstdConfig { level: 19, long: Some(27), ..ZstdConfig::default() });
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to wrap zstd Encoder")]
+    fn invalid_window_log_panics() {
+        let config = ZstdConfig { long: Some(100), ..ZstdConfig::default() };
+        let _: Encoder<Vec<u8>> = Encoder::wrap_with(Vec::new(), config);
+    }
+
+    #[test]
+    fn unwrap_fails_when_frame_cannot_be_written() {
+        let mut buf = [0; 0];
+        let mut encoder = Encoder::wrap(&mut buf[..]);
+        encoder.write_all(b"data").unwrap();
+        let error = match encoder.try_into_inner() {
+            Ok(_)       => panic!("unwrapped into a full buffer"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.error().kind(), ErrorKind::WriteZero);
+        assert_eq!(error.remaining(), 0);
+    }
+}
@@ -0,0 +1,397 @@
+#[cfg(feature = "zstd")]
+use std::io::BufReader;
+use std::io::{self, Read};
+use std::mem;
+
+use {ReadAdapter, UnwrapError};
+
+#[cfg(feature = "bzip2")]
+extern crate bzip2;
+#[cfg(feature = "flate2")]
+extern crate flate2;
+#[cfg(feature = "xz2")]
+extern crate xz2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+
+// The longest magic number of the detected formats, which is xz's.
+const MAGIC_LEN: usize = 6;
+
+/// The compression formats AutoDecompress can detect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Input which matched none of the other formats, and is passed through unchanged.
+    Plain,
+    /// Gzip, decompressed with the `flate2` feature.
+    Gzip,
+    /// Zstandard, decompressed with the `zstd` feature.
+    Zstd,
+    /// Xz, decompressed with the `xz2` feature.
+    Xz,
+    /// Bzip2, decompressed with the `bzip2` feature.
+    Bzip2,
+}
+
+impl Codec {
+    fn detect(magic: &[u8]) -> Codec {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Codec::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Codec::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Codec::Xz
+        } else if magic.starts_with(b"BZh") {
+            Codec::Bzip2
+        } else {
+            Codec::Plain
+        }
+    }
+}
+
+/// A ReadAdapter which detects whether its input is compressed from the magic number at its
+/// start, and decompresses it if so.
+///
+/// Detection happens on the first read, or on a call to `detect`. Each format other than plain
+/// input is only decompressed when its feature is enabled; reading input in a format whose
+/// feature is disabled fails with `ErrorKind::Unsupported`.
+///
+/// The decompressors read ahead of the compressed stream, and that data is dropped when this
+/// adapter is unwrapped. Unwrapping fails if the bytes read to detect the format have not all
+/// been consumed.
+pub struct AutoDecompress<R: Read> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read> AutoDecompress<R> {
+    /// The format which was detected, or None if nothing has been read yet.
+    pub fn codec(&self) -> Option<Codec> {
+        match self.decoder {
+            Decoder::Undetected(_)                  => None,
+            Decoder::Plain(_)                       => Some(Codec::Plain),
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(_)                        => Some(Codec::Gzip),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(_)                        => Some(Codec::Zstd),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(_)                          => Some(Codec::Xz),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(_)                       => Some(Codec::Bzip2),
+            Decoder::Unsupported(codec, _)          => Some(codec),
+            Decoder::Detecting                      => unreachable!(),
+        }
+    }
+
+    /// Read the start of the input to detect its format, if that has not been done yet.
+    pub fn detect(&mut self) -> io::Result<Codec> {
+        let codec = match self.decoder {
+            Decoder::Undetected(ref mut prefix) => {
+                prefix.fill()?;
+                Codec::detect(&prefix.magic[..prefix.len])
+            }
+            _                                   => return Ok(self.codec().unwrap()),
+        };
+        let prefix = mem::replace(&mut self.decoder, Decoder::Detecting).into_prefix();
+        match Decoder::new(prefix, codec) {
+            Ok(decoder)             => self.decoder = decoder,
+            // Leave the format undetected, so the next read retries creating the decoder.
+            Err((error, prefix))    => {
+                self.decoder = Decoder::Undetected(prefix);
+                return Err(error);
+            }
+        }
+        Ok(codec)
+    }
+}
+
+impl<R: Read> Read for AutoDecompress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.detect()?;
+        match self.decoder {
+            Decoder::Plain(ref mut prefix)          => prefix.read(buf),
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(ref mut decoder)          => decoder.read(buf),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(ref mut decoder)          => decoder.read(buf),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(ref mut decoder)            => decoder.read(buf),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(ref mut decoder)         => decoder.read(buf),
+            Decoder::Unsupported(codec, _)          => {
+                let error = format!("{:?} input is not supported without its feature", codec);
+                Err(io::Error::new(io::ErrorKind::Unsupported, error))
+            }
+            Decoder::Undetected(_)                  => unreachable!(),
+            Decoder::Detecting                      => unreachable!(),
+        }
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for AutoDecompress<R> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        AutoDecompress {
+            decoder: Decoder::Undetected(Prefix {
+                reader,
+                magic: [0; MAGIC_LEN],
+                pos: 0,
+                len: 0,
+            }),
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.decoder.prefix().reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.decoder.prefix_mut().reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap AutoDecompress: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.decoder.prefix().remaining();
+        if remaining == 0 {
+            Ok(self.decoder.into_prefix().reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+enum Decoder<R: Read> {
+    Undetected(Prefix<R>),
+    Plain(Prefix<R>),
+    #[cfg(feature = "flate2")]
+    Gzip(flate2::read::MultiGzDecoder<Prefix<R>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::zio::Reader<BufReader<Prefix<R>>, zstd::stream::raw::Decoder<'static>>),
+    #[cfg(feature = "xz2")]
+    Xz(xz2::read::XzDecoder<Prefix<R>>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::read::MultiBzDecoder<Prefix<R>>),
+    Unsupported(Codec, Prefix<R>),
+    // Only held while replacing an undetected decoder.
+    Detecting,
+}
+
+impl<R: Read> Decoder<R> {
+    // Creating the zstd context can fail, and the prefix is handed back if it does so that it
+    // is not lost.
+    fn new(prefix: Prefix<R>, codec: Codec) -> Result<Decoder<R>, (io::Error, Prefix<R>)> {
+        Ok(match codec {
+            Codec::Plain    => Decoder::Plain(prefix),
+            #[cfg(feature = "flate2")]
+            Codec::Gzip     => Decoder::Gzip(flate2::read::MultiGzDecoder::new(prefix)),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd     => match zstd::stream::raw::Decoder::new() {
+                Ok(decoder) => {
+                    let size = zstd::stream::read::Decoder::<BufReader<Prefix<R>>>
+                        ::recommended_output_size();
+                    let reader = BufReader::with_capacity(size, prefix);
+                    Decoder::Zstd(zstd::stream::zio::Reader::new(reader, decoder))
+                }
+                Err(error)  => return Err((error, prefix)),
+            },
+            #[cfg(feature = "xz2")]
+            Codec::Xz       => Decoder::Xz(xz2::read::XzDecoder::new_multi_decoder(prefix)),
+            #[cfg(feature = "bzip2")]
+            Codec::Bzip2    => Decoder::Bzip2(bzip2::read::MultiBzDecoder::new(prefix)),
+            #[allow(unreachable_patterns)]
+            _               => Decoder::Unsupported(codec, prefix),
+        })
+    }
+
+    fn prefix(&self) -> &Prefix<R> {
+        match *self {
+            Decoder::Undetected(ref prefix)     => prefix,
+            Decoder::Plain(ref prefix)          => prefix,
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(ref decoder)          => decoder.get_ref(),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(ref decoder)          => decoder.reader().get_ref(),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(ref decoder)            => decoder.get_ref(),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(ref decoder)         => decoder.get_ref(),
+            Decoder::Unsupported(_, ref prefix) => prefix,
+            Decoder::Detecting                  => unreachable!(),
+        }
+    }
+
+    fn prefix_mut(&mut self) -> &mut Prefix<R> {
+        match *self {
+            Decoder::Undetected(ref mut prefix)     => prefix,
+            Decoder::Plain(ref mut prefix)          => prefix,
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(ref mut decoder)          => decoder.get_mut(),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(ref mut decoder)          => decoder.reader_mut().get_mut(),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(ref mut decoder)            => decoder.get_mut(),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(ref mut decoder)         => decoder.get_mut(),
+            Decoder::Unsupported(_, ref mut prefix) => prefix,
+            Decoder::Detecting                      => unreachable!(),
+        }
+    }
+
+    fn into_prefix(self) -> Prefix<R> {
+        match self {
+            Decoder::Undetected(prefix)     => prefix,
+            Decoder::Plain(prefix)          => prefix,
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(decoder)          => decoder.into_inner(),
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder)          => decoder.into_inner().into_inner(),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(decoder)            => decoder.into_inner(),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(decoder)         => decoder.into_inner(),
+            Decoder::Unsupported(_, prefix) => prefix,
+            Decoder::Detecting              => unreachable!(),
+        }
+    }
+}
+
+// The inner reader, with the bytes read from it to detect the format put back in front.
+struct Prefix<R> {
+    reader: R,
+    magic: [u8; MAGIC_LEN],
+    pos: usize,
+    len: usize,
+}
+
+impl<R: Read> Prefix<R> {
+    fn fill(&mut self) -> io::Result<()> {
+        while self.len < MAGIC_LEN {
+            match self.reader.read(&mut self.magic[self.len..]) {
+                Ok(0)                                                       => break,
+                Ok(n)                                                       => self.len += n,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error)                                                  => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R> Prefix<R> {
+    fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+}
+
+impl<R: Read> Read for Prefix<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos < self.len {
+            let amt = (&self.magic[self.pos..self.len]).read(buf)?;
+            self.pos += amt;
+            Ok(amt)
+        } else {
+            self.reader.read(buf)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    #[cfg(any(feature = "flate2", feature = "xz2", feature = "bzip2"))]
+    use std::io::Write;
+    use ReadAdapter;
+    use super::{AutoDecompress, Codec};
+
+    const DATA: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+    fn decompress(input: &[u8], codec: Codec, expected: &[u8]) {
+        let mut decompress = AutoDecompress::wrap(input);
+        assert_eq!(decompress.codec(), None);
+        let mut decoded = Vec::new();
+        decompress.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, expected);
+        assert_eq!(decompress.codec(), Some(codec));
+    }
+
+    #[test]
+    fn plain_passes_through() {
+        decompress(DATA, Codec::Plain, DATA);
+        // Shorter than the longest magic number, and a prefix of bzip2's.
+        decompress(b"BZ", Codec::Plain, b"BZ");
+        decompress(b"", Codec::Plain, b"");
+    }
+
+    #[test]
+    fn unwrap_after_detection() {
+        let mut decompress = AutoDecompress::wrap(DATA);
+        assert_eq!(decompress.detect().unwrap(), Codec::Plain);
+        let error = match decompress.try_into_inner() {
+            Ok(_)       => panic!("unwrapped with the magic number unread"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.remaining(), 6);
+
+        let mut decompress = error.into_inner();
+        let mut buf = [0; 6];
+        decompress.read_exact(&mut buf).unwrap();
+        assert_eq!(decompress.into_inner(), &DATA[6..]);
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn gzip() {
+        use super::flate2::Compression;
+        use super::flate2::write::GzEncoder;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(DATA).unwrap();
+        let mut encoded = encoder.finish().unwrap();
+        // Concatenated members are all decompressed.
+        encoded.extend_from_slice(&encoded.clone());
+        decompress(&encoded, Codec::Gzip, &[DATA, DATA].concat());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let encoded = super::zstd::stream::encode_all(DATA, 0).unwrap();
+        decompress(&encoded, Codec::Zstd, DATA);
+    }
+
+    #[cfg(feature = "xz2")]
+    #[test]
+    fn xz() {
+        let mut encoder = super::xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(DATA).unwrap();
+        decompress(&encoder.finish().unwrap(), Codec::Xz, DATA);
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2() {
+        use super::bzip2::Compression;
+        use super::bzip2::write::BzEncoder;
+
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(DATA).unwrap();
+        decompress(&encoder.finish().unwrap(), Codec::Bzip2, DATA);
+    }
+
+    #[cfg(not(feature = "bzip2"))]
+    #[test]
+    fn unsupported() {
+        use std::io::ErrorKind;
+
+        let mut decompress = AutoDecompress::wrap(&b"BZh91AY&SY"[..]);
+        let mut buf = [0; 16];
+        assert_eq!(decompress.read(&mut buf).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(decompress.codec(), Some(Codec::Bzip2));
+    }
+}
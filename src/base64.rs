@@ -0,0 +1,413 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+// The length of the lines MIME breaks base64 into.
+const LINE_LEN: usize = 76;
+
+// The most data one write encodes, so that a large write does not hold all of its encoding at
+// once.
+const MAX_WRITE: usize = 6 * 1024;
+
+/// The alphabets base64 can be encoded with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Base64Alphabet {
+    /// The standard alphabet, using `+` and `/`.
+    Standard,
+    /// The URL and filename safe alphabet, using `-` and `_`.
+    UrlSafe,
+}
+
+impl Base64Alphabet {
+    fn symbols(self) -> &'static [u8; 64] {
+        match self {
+            Base64Alphabet::Standard    => {
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+            }
+            Base64Alphabet::UrlSafe     => {
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
+            }
+        }
+    }
+
+    fn decode(self, symbol: u8) -> Option<u8> {
+        match (self, symbol) {
+            (_, b'A'..=b'Z')                    => Some(symbol - b'A'),
+            (_, b'a'..=b'z')                    => Some(symbol - b'a' + 26),
+            (_, b'0'..=b'9')                    => Some(symbol - b'0' + 52),
+            (Base64Alphabet::Standard, b'+')    => Some(62),
+            (Base64Alphabet::Standard, b'/')    => Some(63),
+            (Base64Alphabet::UrlSafe, b'-')     => Some(62),
+            (Base64Alphabet::UrlSafe, b'_')     => Some(63),
+            _                                   => None,
+        }
+    }
+}
+
+/// The configuration for Base64Encoder and Base64Decoder. The default is the standard alphabet,
+/// with padding and without line breaks.
+///
+/// Decoders only use the alphabet: they accept input with or without padding, and skip line
+/// breaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Base64Config {
+    /// The alphabet to encode with.
+    pub alphabet: Base64Alphabet,
+    /// Pad the final group with `=`.
+    pub padding: bool,
+    /// Break lines with CRLF every 76 columns, as MIME does.
+    pub wrap: bool,
+}
+
+impl Default for Base64Config {
+    fn default() -> Base64Config {
+        Base64Config {
+            alphabet: Base64Alphabet::Standard,
+            padding: true,
+            wrap: false,
+        }
+    }
+}
+
+/// A WriteAdapter which base64 encodes the data written to it.
+///
+/// Encoded data is held until the next write or flush, and each write encodes at most 6 KiB of
+/// data. The final partial group, with its padding, can only be written once no more data will
+/// follow, so it is written when the encoder is unwrapped; an encoder which is dropped instead
+/// leaves its output truncated.
+pub struct Base64Encoder<W> {
+    writer: W,
+    config: Base64Config,
+    group: [u8; 3],
+    group_len: usize,
+    out: Vec<u8>,
+    column: usize,
+}
+
+impl<W: Write> Base64Encoder<W> {
+    fn encode_group(&mut self) {
+        let group = (self.group[0] as u32) << 16 | (self.group[1] as u32) << 8
+                  | self.group[2] as u32;
+        let symbols = self.config.alphabet.symbols();
+        for i in 0..self.group_len + 1 {
+            self.push(symbols[(group >> (18 - 6 * i)) as usize & 63]);
+        }
+        if self.config.padding {
+            for _ in self.group_len..3 {
+                self.push(b'=');
+            }
+        }
+        self.group = [0; 3];
+        self.group_len = 0;
+    }
+
+    fn push(&mut self, symbol: u8) {
+        if self.config.wrap && self.column == LINE_LEN {
+            self.out.extend_from_slice(b"\r\n");
+            self.column = 0;
+        }
+        self.out.push(symbol);
+        self.column += 1;
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for Base64Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_out()?;
+        let buf = &buf[..cmp::min(buf.len(), MAX_WRITE)];
+        for &byte in buf {
+            self.group[self.group_len] = byte;
+            self.group_len += 1;
+            if self.group_len == 3 {
+                self.encode_group();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for Base64Encoder<W> {
+    type Config = Base64Config;
+
+    fn wrap_with(writer: W, config: Base64Config) -> Self {
+        Base64Encoder {
+            writer,
+            config,
+            group: [0; 3],
+            group_len: 0,
+            out: Vec::new(),
+            column: 0,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap Base64Encoder: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        if self.group_len > 0 {
+            self.encode_group();
+        }
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+/// A ReadAdapter which decodes base64 read from its inner Read.
+///
+/// The input is read ahead in chunks, so unwrapping fails if the decoder holds input or decoded
+/// data which has not been read from it yet.
+pub struct Base64Decoder<R> {
+    reader: R,
+    alphabet: Base64Alphabet,
+    input: Box<[u8]>,
+    in_pos: usize,
+    in_len: usize,
+    quad: [u8; 4],
+    quad_len: usize,
+    padded: bool,
+    out: [u8; 3],
+    out_pos: usize,
+    out_len: usize,
+}
+
+impl<R: Read> Base64Decoder<R> {
+    fn push(&mut self, symbol: u8) -> io::Result<()> {
+        match symbol {
+            b'\r' | b'\n'                               => {}
+            b'=' if self.quad_len >= 2                  => {
+                self.decode_quad();
+                self.padded = true;
+            }
+            b'=' if self.quad_len == 0 && self.padded   => {}
+            _                                           => {
+                let value = match self.alphabet.decode(symbol) {
+                    Some(value) => value,
+                    None        => return Err(invalid(symbol)),
+                };
+                self.quad[self.quad_len] = value;
+                self.quad_len += 1;
+                self.padded = false;
+                if self.quad_len == 4 {
+                    self.decode_quad();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_quad(&mut self) {
+        let mut quad = 0;
+        for i in 0..4 {
+            let value = if i < self.quad_len { self.quad[i] as u32 } else { 0 };
+            quad = quad << 6 | value;
+        }
+        self.out = [(quad >> 16) as u8, (quad >> 8) as u8, quad as u8];
+        self.out_pos = 0;
+        self.out_len = self.quad_len - 1;
+        self.quad_len = 0;
+    }
+}
+
+impl<R: Read> Read for Base64Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.out_pos < self.out_len {
+                let amt = cmp::min(self.out_len - self.out_pos, buf.len() - n);
+                buf[n..n + amt].copy_from_slice(&self.out[self.out_pos..self.out_pos + amt]);
+                self.out_pos += amt;
+                n += amt;
+            } else if self.in_pos < self.in_len {
+                let symbol = self.input[self.in_pos];
+                if let Err(error) = self.push(symbol) {
+                    // The invalid symbol stays unread, so every later read fails on it, after
+                    // the data decoded before it has been returned.
+                    if n == 0 {
+                        return Err(error);
+                    }
+                    break;
+                }
+                self.in_pos += 1;
+            } else if n > 0 {
+                break;
+            } else {
+                self.in_len = self.reader.read(&mut self.input)?;
+                self.in_pos = 0;
+                if self.in_len == 0 {
+                    match self.quad_len {
+                        0       => break,
+                        1       => {
+                            let error = "base64 input ended in the middle of a group";
+                            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                        }
+                        _       => self.decode_quad(),
+                    }
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for Base64Decoder<R> {
+    type Config = Base64Config;
+
+    fn wrap_with(reader: R, config: Base64Config) -> Self {
+        Base64Decoder {
+            reader,
+            alphabet: config.alphabet,
+            input: vec![0; 8 * 1024].into_boxed_slice(),
+            in_pos: 0,
+            in_len: 0,
+            quad: [0; 4],
+            quad_len: 0,
+            padded: false,
+            out: [0; 3],
+            out_pos: 0,
+            out_len: 0,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap Base64Decoder: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.in_len - self.in_pos + self.quad_len + self.out_len - self.out_pos;
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+fn invalid(symbol: u8) -> io::Error {
+    let error = format!("invalid base64 symbol {:#04x}", symbol);
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{Base64Alphabet, Base64Config, Base64Decoder, Base64Encoder};
+
+    #[test]
+    fn decoded_data_before_invalid_symbol() {
+        let mut decoder = Base64Decoder::wrap(&b"QUJD!!"[..]);
+        let mut buf = [0; 16];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ABC");
+        assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_symbol_fails_every_retry() {
+        let mut decoder = Base64Decoder::wrap(&b"QUJD\r\nRE!V"[..]);
+        let mut buf = [0; 2];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 2);
+        assert_eq!(decoder.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[..1], *b"C");
+        for _ in 0..3 {
+            assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for &alphabet in &[Base64Alphabet::Standard, Base64Alphabet::UrlSafe] {
+            for &padding in &[true, false] {
+                for len in 0..4 {
+                    let data = &data[..data.len() - len];
+                    let config = Base64Config { alphabet, padding, wrap: true };
+                    let mut encoder = Base64Encoder::wrap_with(Vec::new(), config);
+                    encoder.write_all(data).unwrap();
+                    let encoded = encoder.into_inner();
+                    assert_eq!(&encoded[76..78], b"\r\n");
+                    assert_eq!(encoded.ends_with(b"="), padding && !data.len().is_multiple_of(3));
+
+                    let mut decoded = Vec::new();
+                    let mut decoder = Base64Decoder::wrap_with(&encoded[..], config);
+                    decoder.read_to_end(&mut decoded).unwrap();
+                    assert_eq!(decoded, data);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_encodings() {
+        let mut encoder = Base64Encoder::wrap(Vec::new());
+        encoder.write_all(b"\xfb\xff").unwrap();
+        assert_eq!(encoder.into_inner(), b"+/8=");
+
+        let config = Base64Config {
+            alphabet: Base64Alphabet::UrlSafe,
+            padding: false,
+            wrap: false,
+        };
+        let mut encoder = Base64Encoder::wrap_with(Vec::new(), config);
+        encoder.write_all(b"\xfb\xff").unwrap();
+        assert_eq!(encoder.into_inner(), b"-_8");
+
+        // Each alphabet rejects the other's symbols.
+        let mut decoder = Base64Decoder::wrap_with(&b"+/8="[..], config);
+        assert_eq!(decoder.read(&mut [0; 4]).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn large_write_is_encoded_in_parts() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut encoder = Base64Encoder::wrap(Vec::new());
+        let n = encoder.write(&data).unwrap();
+        assert_eq!(n, 6 * 1024);
+        encoder.write_all(&data[n..]).unwrap();
+        let encoded = encoder.into_inner();
+
+        let mut decoded = Vec::new();
+        Base64Decoder::wrap(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,367 @@
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+
+/// An object safe version of ReadAdapter, implemented for every ReadAdapter which is also Read.
+/// `Box<dyn DynReadAdapter<R>>` can hold any such adapter over `R`, chosen at runtime.
+///
+/// The borrowing accessors are named differently from ReadAdapter's, so that calls to either are
+/// not ambiguous when both traits are in scope.
+pub trait DynReadAdapter<R>: Read {
+    /// Get a reference to the inner Read.
+    fn inner_ref(&self) -> &R;
+
+    /// Get a mutable reference to the inner Read. Reading from it directly may corrupt the
+    /// state of this adapter.
+    fn inner_mut(&mut self) -> &mut R;
+
+    /// Unwrap this boxed adapter to get its inner Read. If this action could fail, this call
+    /// should panic on fail.
+    fn into_inner(self: Box<Self>) -> R;
+
+    /// Try to unwrap this boxed adapter, yielding an UnwrapError holding it if it fails.
+    fn try_into_inner(self: Box<Self>) -> Result<R, UnwrapError<Box<dyn DynReadAdapter<R>>>>;
+}
+
+impl<R, A: ReadAdapter<R> + Read + 'static> DynReadAdapter<R> for A {
+    fn inner_ref(&self) -> &R {
+        ReadAdapter::get_ref(self)
+    }
+
+    fn inner_mut(&mut self) -> &mut R {
+        ReadAdapter::get_mut(self)
+    }
+
+    fn into_inner(self: Box<Self>) -> R {
+        ReadAdapter::into_inner(*self)
+    }
+
+    fn try_into_inner(self: Box<Self>) -> Result<R, UnwrapError<Box<dyn DynReadAdapter<R>>>> {
+        ReadAdapter::try_into_inner(*self).map_err(|error| {
+            error.map(|adapter| Box::new(adapter) as Box<dyn DynReadAdapter<R>>)
+        })
+    }
+}
+
+/// An object safe version of WriteAdapter, implemented for every WriteAdapter which is also
+/// Write. `Box<dyn DynWriteAdapter<W>>` can hold any such adapter over `W`, chosen at runtime.
+///
+/// The borrowing accessors are named differently from WriteAdapter's, so that calls to either are
+/// not ambiguous when both traits are in scope.
+pub trait DynWriteAdapter<W>: Write {
+    /// Get a reference to the inner Write.
+    fn inner_ref(&self) -> &W;
+
+    /// Get a mutable reference to the inner Write. Writing to it directly may corrupt the
+    /// state of this adapter.
+    fn inner_mut(&mut self) -> &mut W;
+
+    /// Unwrap this boxed adapter to get its inner Write. If this action could fail, this call
+    /// should panic on fail.
+    fn into_inner(self: Box<Self>) -> W;
+
+    /// Try to unwrap this boxed adapter, yielding an UnwrapError holding it if it fails.
+    fn try_into_inner(self: Box<Self>) -> Result<W, UnwrapError<Box<dyn DynWriteAdapter<W>>>>;
+}
+
+impl<W, A: WriteAdapter<W> + Write + 'static> DynWriteAdapter<W> for A {
+    fn inner_ref(&self) -> &W {
+        WriteAdapter::get_ref(self)
+    }
+
+    fn inner_mut(&mut self) -> &mut W {
+        WriteAdapter::get_mut(self)
+    }
+
+    fn into_inner(self: Box<Self>) -> W {
+        WriteAdapter::into_inner(*self)
+    }
+
+    fn try_into_inner(self: Box<Self>) -> Result<W, UnwrapError<Box<dyn DynWriteAdapter<W>>>> {
+        WriteAdapter::try_into_inner(*self).map_err(|error| {
+            error.map(|adapter| Box::new(adapter) as Box<dyn DynWriteAdapter<W>>)
+        })
+    }
+}
+
+/// A ReadAdapter holding a `Box<dyn DynReadAdapter<R>>`. Its configuration chooses the adapter it
+/// wraps with, so it can stand in for any adapter in generic code and in a Stack.
+pub struct BoxedReadAdapter<R> {
+    adapter: Box<dyn DynReadAdapter<R>>,
+}
+
+impl<R> BoxedReadAdapter<R> {
+    /// Box an adapter which has already been wrapped.
+    pub fn new<A: DynReadAdapter<R> + 'static>(adapter: A) -> BoxedReadAdapter<R> {
+        BoxedReadAdapter { adapter: Box::new(adapter) }
+    }
+
+    /// Get the boxed adapter.
+    pub fn into_box(self) -> Box<dyn DynReadAdapter<R>> {
+        self.adapter
+    }
+}
+
+impl<R> From<Box<dyn DynReadAdapter<R>>> for BoxedReadAdapter<R> {
+    fn from(adapter: Box<dyn DynReadAdapter<R>>) -> BoxedReadAdapter<R> {
+        BoxedReadAdapter { adapter }
+    }
+}
+
+impl<R> Read for BoxedReadAdapter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.adapter.read(buf)
+    }
+}
+
+impl<R: Read + 'static> ReadAdapter<R> for BoxedReadAdapter<R> {
+    type Config = BoxedReadConfig<R>;
+
+    fn wrap_with(reader: R, config: BoxedReadConfig<R>) -> Self {
+        BoxedReadAdapter { adapter: (config.wrap)(reader) }
+    }
+
+    fn get_ref(&self) -> &R {
+        self.adapter.inner_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        self.adapter.inner_mut()
+    }
+
+    fn into_inner(self) -> R {
+        self.adapter.into_inner()
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        self.adapter.try_into_inner().map_err(|error| error.map(BoxedReadAdapter::from))
+    }
+}
+
+/// The configuration of a BoxedReadAdapter: which adapter to wrap with, and that adapter's own
+/// configuration. The default passes reads straight through to the inner Read.
+pub struct BoxedReadConfig<R> {
+    wrap: Box<dyn FnOnce(R) -> Box<dyn DynReadAdapter<R>>>,
+}
+
+impl<R: 'static> BoxedReadConfig<R> {
+    /// Wrap with the adapter `A`, using the given configuration.
+    pub fn new<A>(config: A::Config) -> BoxedReadConfig<R>
+        where A: ReadAdapter<R> + Read + 'static, A::Config: 'static
+    {
+        BoxedReadConfig {
+            wrap: Box::new(move |reader| Box::new(A::wrap_with(reader, config))),
+        }
+    }
+}
+
+impl<R: Read + 'static> Default for BoxedReadConfig<R> {
+    fn default() -> BoxedReadConfig<R> {
+        BoxedReadConfig::new::<Passthrough<R>>(())
+    }
+}
+
+/// A WriteAdapter holding a `Box<dyn DynWriteAdapter<W>>`. Its configuration chooses the adapter
+/// it wraps with, so it can stand in for any adapter in generic code and in a Stack.
+pub struct BoxedWriteAdapter<W> {
+    adapter: Box<dyn DynWriteAdapter<W>>,
+}
+
+impl<W> BoxedWriteAdapter<W> {
+    /// Box an adapter which has already been wrapped.
+    pub fn new<A: DynWriteAdapter<W> + 'static>(adapter: A) -> BoxedWriteAdapter<W> {
+        BoxedWriteAdapter { adapter: Box::new(adapter) }
+    }
+
+    /// Get the boxed adapter.
+    pub fn into_box(self) -> Box<dyn DynWriteAdapter<W>> {
+        self.adapter
+    }
+}
+
+impl<W> From<Box<dyn DynWriteAdapter<W>>> for BoxedWriteAdapter<W> {
+    fn from(adapter: Box<dyn DynWriteAdapter<W>>) -> BoxedWriteAdapter<W> {
+        BoxedWriteAdapter { adapter }
+    }
+}
+
+impl<W> Write for BoxedWriteAdapter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.adapter.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.adapter.flush()
+    }
+}
+
+impl<W: Write + 'static> WriteAdapter<W> for BoxedWriteAdapter<W> {
+    type Config = BoxedWriteConfig<W>;
+
+    fn wrap_with(writer: W, config: BoxedWriteConfig<W>) -> Self {
+        BoxedWriteAdapter { adapter: (config.wrap)(writer) }
+    }
+
+    fn get_ref(&self) -> &W {
+        self.adapter.inner_ref()
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        self.adapter.inner_mut()
+    }
+
+    fn into_inner(self) -> W {
+        self.adapter.into_inner()
+    }
+
+    fn try_into_inner(self) -> Result<W, UnwrapError<Self>> {
+        self.adapter.try_into_inner().map_err(|error| error.map(BoxedWriteAdapter::from))
+    }
+}
+
+/// The configuration of a BoxedWriteAdapter: which adapter to wrap with, and that adapter's own
+/// configuration. The default passes writes straight through to the inner Write.
+pub struct BoxedWriteConfig<W> {
+    wrap: Box<dyn FnOnce(W) -> Box<dyn DynWriteAdapter<W>>>,
+}
+
+impl<W: 'static> BoxedWriteConfig<W> {
+    /// Wrap with the adapter `A`, using the given configuration.
+    pub fn new<A>(config: A::Config) -> BoxedWriteConfig<W>
+        where A: WriteAdapter<W> + Write + 'static, A::Config: 'static
+    {
+        BoxedWriteConfig {
+            wrap: Box::new(move |writer| Box::new(A::wrap_with(writer, config))),
+        }
+    }
+}
+
+impl<W: Write + 'static> Default for BoxedWriteConfig<W> {
+    fn default() -> BoxedWriteConfig<W> {
+        BoxedWriteConfig::new::<Passthrough<W>>(())
+    }
+}
+
+// The adapter a default boxed configuration wraps with, which does nothing.
+struct Passthrough<T>(T);
+
+impl<R: Read> Read for Passthrough<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<W: Write> Write for Passthrough<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for Passthrough<R> {
+    type Config = ();
+
+    fn wrap_with(reader: R, _: ()) -> Self {
+        Passthrough(reader)
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.0
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.0
+    }
+
+    fn into_inner(self) -> R {
+        self.0
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for Passthrough<W> {
+    type Config = ();
+
+    fn wrap_with(writer: W, _: ()) -> Self {
+        Passthrough(writer)
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.0
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.0
+    }
+
+    fn into_inner(self) -> W {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader, BufWriter, Read, Write};
+    use {BufConfig, ReadAdapter, WriteAdapter};
+    use super::{BoxedReadAdapter, BoxedReadConfig, BoxedWriteAdapter};
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn boxed_writer() {
+        let mut adapter = BoxedWriteAdapter::new(BufWriter::new(Vec::new()));
+        adapter.write_all(b"data").unwrap();
+        assert!(adapter.get_ref().is_empty());
+        adapter.get_mut().extend_from_slice(b"raw ");
+        match adapter.try_into_inner() {
+            Ok(writer)  => assert_eq!(writer, b"raw data"),
+            Err(error)  => panic!("Failed to unwrap BoxedWriteAdapter: {:?}", error.error()),
+        }
+
+        let mut adapter = BoxedWriteAdapter::new(BufWriter::new(Broken));
+        adapter.write_all(b"data").unwrap();
+        let error = match adapter.try_into_inner() {
+            Ok(_)       => panic!("unwrapped over a broken writer"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.remaining(), 4);
+        // The adapter is still boxed in the error, holding the data it failed to write.
+        let mut adapter = error.into_inner().into_box();
+        assert!(adapter.flush().is_err());
+    }
+
+    #[test]
+    fn boxed_reader() {
+        let config = BoxedReadConfig::new::<BufReader<_>>(BufConfig { capacity: 4 });
+        let mut adapter = BoxedReadAdapter::wrap_with(&b"boxed data"[..], config);
+        let mut buf = [0; 2];
+        adapter.read_exact(&mut buf).unwrap();
+        assert_eq!(adapter.get_ref(), b"d data");
+        *adapter.get_mut() = &b"more"[..];
+        let mut rest = Vec::new();
+        adapter.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"xemore");
+        assert!(adapter.into_inner().is_empty());
+
+        // The default passes reads straight through.
+        let mut adapter = BoxedReadAdapter::wrap(&b"data"[..]);
+        adapter.read_exact(&mut buf).unwrap();
+        assert_eq!(adapter.get_ref(), b"ta");
+        match adapter.try_into_inner() {
+            Ok(reader)  => assert_eq!(reader, b"ta"),
+            Err(error)  => panic!("Failed to unwrap BoxedReadAdapter: {:?}", error.error()),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::Arc;
+
+use {BufConfig, DuplexAdapter, UnwrapError};
+use error::write_out;
+
+/// The configuration for a `BufStream` adapter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufStreamConfig {
+    /// The configuration of the read buffer.
+    pub read: BufConfig,
+    /// The configuration of the write buffer.
+    pub write: BufConfig,
+}
+
+/// A stream with separate buffers for reading and writing.
+///
+/// Unwrapping the stream flushes the write buffer, and discards the read buffer as `BufReader`
+/// does. Only flushing and unwrapping write out the write buffer; whatever it holds when the
+/// stream is dropped is lost.
+pub struct BufStream<S> {
+    stream: S,
+    read_buf: ReadBuf,
+    write_buf: WriteBuf,
+}
+
+impl<S> BufStream<S> where for<'a> &'a S: Read + Write {
+    /// Split this stream into a read half and a write half, which keep their buffers and share
+    /// the stream between them.
+    ///
+    /// The halves read and write through a shared reference to the stream, as `TcpStream`
+    /// allows, so one half can be blocked in a read while the other writes.
+    pub fn split(self) -> (ReadHalf<S>, WriteHalf<S>) {
+        let stream = Arc::new(self.stream);
+        let read = ReadHalf { stream: stream.clone(), buf: self.read_buf };
+        let write = WriteHalf { stream, buf: self.write_buf };
+        (read, write)
+    }
+}
+
+impl<S: Read + Write> DuplexAdapter<S> for BufStream<S> {
+    type Config = BufStreamConfig;
+
+    fn wrap_with(stream: S, config: BufStreamConfig) -> Self {
+        BufStream {
+            stream,
+            read_buf: ReadBuf::new(config.read.capacity),
+            write_buf: WriteBuf::new(config.write.capacity),
+        }
+    }
+
+    fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    fn into_inner(self) -> S {
+        match self.try_into_inner() {
+            Ok(stream)  => stream,
+            Err(error)  => panic!("Failed to unwrap BufStream: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<S, UnwrapError<Self>> {
+        match self.write_buf.flush_buf(&mut self.stream) {
+            Ok(())      => Ok(self.stream),
+            Err(error)  => {
+                let remaining = self.write_buf.buf.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+impl<S: Read> Read for BufStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_buf.read(&mut self.stream, buf)
+    }
+}
+
+impl<S: Read> BufRead for BufStream<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.read_buf.fill(&mut self.stream)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read_buf.consume(amt)
+    }
+}
+
+impl<S: Write> Write for BufStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf.write(&mut self.stream, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buf.flush_buf(&mut self.stream)?;
+        self.stream.flush()
+    }
+}
+
+/// The read half of a split `BufStream`.
+pub struct ReadHalf<S> {
+    stream: Arc<S>,
+    buf: ReadBuf,
+}
+
+impl<S> ReadHalf<S> {
+    /// Join this half with the write half it was split from, to get the `BufStream` back.
+    ///
+    /// This panics if the halves were not split from the same `BufStream`.
+    pub fn unsplit(self, write: WriteHalf<S>) -> BufStream<S> {
+        assert!(Arc::ptr_eq(&self.stream, &write.stream), "Unsplit halves of different BufStreams");
+        drop(write.stream);
+        let stream = match Arc::try_unwrap(self.stream) {
+            Ok(stream)  => stream,
+            Err(_)      => unreachable!(),
+        };
+        BufStream { stream, read_buf: self.buf, write_buf: write.buf }
+    }
+}
+
+impl<S> Read for ReadHalf<S> where for<'a> &'a S: Read {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.buf.read(&mut &*self.stream, buf)
+    }
+}
+
+impl<S> BufRead for ReadHalf<S> where for<'a> &'a S: Read {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.buf.fill(&mut &*self.stream)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf.consume(amt)
+    }
+}
+
+/// The write half of a split `BufStream`.
+pub struct WriteHalf<S> {
+    stream: Arc<S>,
+    buf: WriteBuf,
+}
+
+impl<S> Write for WriteHalf<S> where for<'a> &'a S: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.write(&mut &*self.stream, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buf.flush_buf(&mut &*self.stream)?;
+        (&*self.stream).flush()
+    }
+}
+
+struct ReadBuf {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+}
+
+impl ReadBuf {
+    fn new(capacity: usize) -> ReadBuf {
+        ReadBuf { buf: vec![0; capacity].into_boxed_slice(), pos: 0, cap: 0 }
+    }
+
+    fn fill<R: Read + ?Sized>(&mut self, reader: &mut R) -> io::Result<&[u8]> {
+        if self.pos >= self.cap {
+            self.cap = reader.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+    }
+
+    fn read<R: Read + ?Sized>(&mut self, reader: &mut R, out: &mut [u8]) -> io::Result<usize> {
+        // Bypass the buffer entirely for reads at least as large as it.
+        if self.pos >= self.cap && out.len() >= self.buf.len() {
+            return reader.read(out);
+        }
+        let amt = {
+            let available = self.fill(reader)?;
+            let amt = cmp::min(available.len(), out.len());
+            out[..amt].copy_from_slice(&available[..amt]);
+            amt
+        };
+        self.consume(amt);
+        Ok(amt)
+    }
+}
+
+struct WriteBuf {
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+impl WriteBuf {
+    fn new(capacity: usize) -> WriteBuf {
+        WriteBuf { buf: Vec::with_capacity(capacity), capacity }
+    }
+
+    fn write<W: Write + ?Sized>(&mut self, writer: &mut W, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > self.capacity {
+            self.flush_buf(writer)?;
+        }
+        if data.len() >= self.capacity {
+            writer.write(data)
+        } else {
+            self.buf.extend_from_slice(data);
+            Ok(data.len())
+        }
+    }
+
+    fn flush_buf<W: Write + ?Sized>(&mut self, writer: &mut W) -> io::Result<()> {
+        write_out(writer, &mut self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use DuplexAdapter;
+    use super::BufStream;
+
+    #[test]
+    fn halves_read_and_write_at_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"ping");
+            stream.write_all(b"pong").unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        // A half which blocked the other would time out rather than hang the test.
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let (mut read, mut write) = BufStream::wrap(stream).split();
+        let reader = thread::spawn(move || {
+            let mut response = [0; 4];
+            read.read_exact(&mut response).map(|()| (read, response))
+        });
+
+        // Give the reader time to block on the empty socket before writing the request.
+        thread::sleep(Duration::from_millis(50));
+        write.write_all(b"ping").unwrap();
+        write.flush().unwrap();
+
+        let (read, response) = reader.join().unwrap().unwrap();
+        assert_eq!(&response, b"pong");
+        server.join().unwrap();
+        read.unsplit(write).into_inner();
+    }
+}
@@ -0,0 +1,475 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+/// The configuration for the COBS and SLIP encoders and decoders. The default is frames of up
+/// to 64 KiB, without a leading delimiter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteStuffingConfig {
+    /// The largest frame, in bytes, before it is encoded. Decoders skip larger frames, and
+    /// encoders refuse to write them.
+    pub max_frame_size: usize,
+    /// Write a delimiter before each frame as well as after it, so the receiver drops any noise
+    /// on the line before the frame instead of taking it as the start of the frame. Decoders
+    /// ignore this, since they skip empty frames.
+    pub leading_delimiter: bool,
+}
+
+impl Default for ByteStuffingConfig {
+    fn default() -> ByteStuffingConfig {
+        ByteStuffingConfig {
+            max_frame_size: 64 * 1024,
+            leading_delimiter: false,
+        }
+    }
+}
+
+// Both encoders write each frame as a whole. If that fails part way, the rest is held, and
+// written before the next frame or when the encoder is flushed or unwrapped.
+macro_rules! encoder {
+    ($(#[$attr:meta])* $encoder:ident, $encode:ident, $delimiter:expr) => {
+        $(#[$attr])*
+        pub struct $encoder<W> {
+            writer: W,
+            config: ByteStuffingConfig,
+            out: Vec<u8>,
+        }
+
+        impl<W: Write> $encoder<W> {
+            /// Encode a frame and write it, followed by the delimiter. This fails with
+            /// `ErrorKind::InvalidInput`, without writing anything, if the frame is larger than
+            /// the maximum frame size.
+            pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+                self.flush_out()?;
+                if frame.len() > self.config.max_frame_size {
+                    let error = format!("frame of {} bytes is too large to write", frame.len());
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+                }
+                if self.config.leading_delimiter {
+                    self.out.push($delimiter);
+                }
+                $encode(frame, &mut self.out);
+                self.out.push($delimiter);
+                self.flush_out()
+            }
+
+            /// Flush the inner Write, after writing out any frame held by a failed write.
+            pub fn flush(&mut self) -> io::Result<()> {
+                self.flush_out()?;
+                self.writer.flush()
+            }
+
+            fn flush_out(&mut self) -> io::Result<()> {
+                write_out(&mut self.writer, &mut self.out)
+            }
+        }
+
+        impl<W: Write> WriteAdapter<W> for $encoder<W> {
+            type Config = ByteStuffingConfig;
+
+            fn wrap_with(writer: W, config: ByteStuffingConfig) -> Self {
+                $encoder {
+                    writer,
+                    config,
+                    out: Vec::new(),
+                }
+            }
+
+            fn get_ref(&self) -> &W {
+                &self.writer
+            }
+
+            fn get_mut(&mut self) -> &mut W {
+                &mut self.writer
+            }
+
+            fn into_inner(self) -> W {
+                match self.try_into_inner() {
+                    Ok(writer)  => writer,
+                    Err(error)  => {
+                        panic!(concat!("Failed to unwrap ", stringify!($encoder), ": {:?}"),
+                               error.error())
+                    }
+                }
+            }
+
+            fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+                match self.flush() {
+                    Ok(())      => Ok(self.writer),
+                    Err(error)  => {
+                        let remaining = self.out.len();
+                        Err(UnwrapError::new(self, error, remaining))
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Both decoders read ahead in chunks and collect the encoded bytes of a frame up to its
+// delimiter before decoding it, so a corrupted or oversized frame is skipped as a whole and
+// decoding resumes at the next frame. Empty frames, from repeated delimiters, are skipped.
+macro_rules! decoder {
+    ($(#[$attr:meta])* $decoder:ident, $decode:ident, $delimiter:expr, $max_encoded:expr) => {
+        $(#[$attr])*
+        pub struct $decoder<R> {
+            reader: R,
+            max_frame_size: usize,
+            input: Box<[u8]>,
+            in_pos: usize,
+            in_len: usize,
+            frame: Vec<u8>,
+            oversized: bool,
+        }
+
+        impl<R> $decoder<R> {
+            /// Unwrap this decoder, returning the inner Read and the input it has buffered, from
+            /// the start of the frame it was in the middle of reading.
+            pub fn into_parts(self) -> (R, Vec<u8>) {
+                let mut buffered = self.frame;
+                buffered.extend_from_slice(&self.input[self.in_pos..self.in_len]);
+                (self.reader, buffered)
+            }
+        }
+
+        impl<R: Read> $decoder<R> {
+            /// Read and decode the next frame. This returns `None` at the end of the input.
+            ///
+            /// A frame which cannot be decoded, or which is larger than the maximum frame size,
+            /// fails with `ErrorKind::InvalidData`, and the next call reads the frame after it.
+            /// Input which ends in the middle of a frame fails with `ErrorKind::UnexpectedEof`.
+            pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+                let max_encoded: fn(usize) -> usize = $max_encoded;
+                let max_encoded = max_encoded(self.max_frame_size);
+                loop {
+                    if self.in_pos == self.in_len {
+                        self.in_len = match self.reader.read(&mut self.input) {
+                            Ok(n)       => n,
+                            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {
+                                continue
+                            }
+                            Err(error)  => return Err(error),
+                        };
+                        self.in_pos = 0;
+                        if self.in_len == 0 {
+                            if self.frame.is_empty() && !self.oversized {
+                                return Ok(None);
+                            }
+                            self.frame.clear();
+                            self.oversized = false;
+                            let error = "input ended in the middle of a frame";
+                            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                        }
+                    }
+
+                    let input = &self.input[self.in_pos..self.in_len];
+                    let (chunk, end) = match input.iter().position(|&byte| byte == $delimiter) {
+                        Some(end)   => (&input[..end], true),
+                        None        => (input, false),
+                    };
+                    self.in_pos += chunk.len() + end as usize;
+                    if !self.oversized {
+                        if self.frame.len() + chunk.len() > max_encoded {
+                            self.frame.clear();
+                            self.oversized = true;
+                        } else {
+                            self.frame.extend_from_slice(chunk);
+                        }
+                    }
+                    if !end {
+                        continue;
+                    }
+
+                    let frame = mem::take(&mut self.frame);
+                    if mem::replace(&mut self.oversized, false) {
+                        return Err(too_large(self.max_frame_size));
+                    }
+                    if frame.is_empty() {
+                        continue;
+                    }
+                    let decoded = match $decode(&frame) {
+                        Ok(decoded) => decoded,
+                        Err(error)  => {
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                        }
+                    };
+                    if decoded.len() > self.max_frame_size {
+                        return Err(too_large(self.max_frame_size));
+                    }
+                    return Ok(Some(decoded));
+                }
+            }
+        }
+
+        impl<R: Read> ReadAdapter<R> for $decoder<R> {
+            type Config = ByteStuffingConfig;
+
+            fn wrap_with(reader: R, config: ByteStuffingConfig) -> Self {
+                $decoder {
+                    reader,
+                    max_frame_size: config.max_frame_size,
+                    input: vec![0; 8 * 1024].into_boxed_slice(),
+                    in_pos: 0,
+                    in_len: 0,
+                    frame: Vec::new(),
+                    oversized: false,
+                }
+            }
+
+            fn get_ref(&self) -> &R {
+                &self.reader
+            }
+
+            fn get_mut(&mut self) -> &mut R {
+                &mut self.reader
+            }
+
+            fn into_inner(self) -> R {
+                match self.try_into_inner() {
+                    Ok(reader)  => reader,
+                    Err(error)  => {
+                        panic!(concat!("Failed to unwrap ", stringify!($decoder), ": {:?}"),
+                               error.error())
+                    }
+                }
+            }
+
+            fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+                let remaining = self.frame.len() + self.in_len - self.in_pos;
+                if remaining == 0 {
+                    Ok(self.reader)
+                } else {
+                    let error = format!("{} bytes were read ahead of the reader", remaining);
+                    Err(UnwrapError::new(self, io::Error::other(error), remaining))
+                }
+            }
+        }
+    }
+}
+
+encoder! {
+    /// A WriteAdapter which writes frames with Consistent Overhead Byte Stuffing, each followed
+    /// by a zero byte.
+    CobsEncoder, cobs_encode, 0
+}
+
+decoder! {
+    /// A ReadAdapter which reads zero delimited frames encoded with Consistent Overhead Byte
+    /// Stuffing.
+    CobsDecoder, cobs_decode, 0, |max| max.saturating_add(max / 254 + 1)
+}
+
+encoder! {
+    /// A WriteAdapter which writes frames with SLIP (RFC 1055) byte stuffing, each followed by
+    /// an END byte.
+    SlipEncoder, slip_encode, SLIP_END
+}
+
+decoder! {
+    /// A ReadAdapter which reads END delimited frames encoded with SLIP (RFC 1055).
+    SlipDecoder, slip_decode, SLIP_END, |max| max.saturating_mul(2)
+}
+
+// Each block starts with a code byte: one more than the number of non-zero bytes it holds.
+// Blocks with fewer than 254 of them stand for those bytes followed by a zero; the last zero is
+// implied by the end of the frame. A full block only starts another if more bytes follow.
+fn cobs_encode(frame: &[u8], out: &mut Vec<u8>) {
+    let mut code_pos = out.len();
+    let mut code = 1;
+    out.push(0);
+    for (i, &byte) in frame.iter().enumerate() {
+        if byte != 0 {
+            out.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xff && i + 1 < frame.len() {
+            out[code_pos] = code;
+            code_pos = out.len();
+            code = 1;
+            out.push(0);
+        }
+    }
+    out[code_pos] = code;
+}
+
+fn cobs_decode(frame: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut decoded = Vec::with_capacity(frame.len());
+    let mut pos = 0;
+    while pos < frame.len() {
+        let code = frame[pos] as usize;
+        if pos + code > frame.len() {
+            return Err("COBS block runs past the end of the frame");
+        }
+        decoded.extend_from_slice(&frame[pos + 1..pos + code]);
+        pos += code;
+        if code < 0xff && pos < frame.len() {
+            decoded.push(0);
+        }
+    }
+    Ok(decoded)
+}
+
+fn slip_encode(frame: &[u8], out: &mut Vec<u8>) {
+    for &byte in frame {
+        match byte {
+            SLIP_END    => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC    => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            _           => out.push(byte),
+        }
+    }
+}
+
+fn slip_decode(frame: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut decoded = Vec::with_capacity(frame.len());
+    let mut bytes = frame.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != SLIP_ESC {
+            decoded.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(&SLIP_ESC_END) => decoded.push(SLIP_END),
+            Some(&SLIP_ESC_ESC) => decoded.push(SLIP_ESC),
+            _                   => return Err("invalid SLIP escape sequence"),
+        }
+    }
+    Ok(decoded)
+}
+
+fn too_large(max: usize) -> io::Error {
+    let error = format!("frame is larger than the maximum of {} bytes", max);
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use {ReadAdapter, WriteAdapter};
+    use super::{ByteStuffingConfig, CobsDecoder, CobsEncoder, SlipDecoder, SlipEncoder};
+    use super::{SLIP_END, SLIP_ESC};
+
+    fn cobs(frame: &[u8]) -> Vec<u8> {
+        let mut encoder = CobsEncoder::wrap(Vec::new());
+        encoder.write_frame(frame).unwrap();
+        encoder.into_inner()
+    }
+
+    #[test]
+    fn cobs_reference_encodings() {
+        let nonzero: Vec<u8> = (1..=255).collect();
+        let mut block = vec![0xff];
+        block.extend_from_slice(&nonzero[..254]);
+
+        assert_eq!(cobs(&[]), [0x01, 0x00]);
+        assert_eq!(cobs(&[0x00]), [0x01, 0x01, 0x00]);
+        assert_eq!(cobs(&[0x11, 0x22, 0x00, 0x33]), [0x03, 0x11, 0x22, 0x02, 0x33, 0x00]);
+
+        // A frame of exactly 254 non-zero bytes is a single full block.
+        assert_eq!(cobs(&nonzero[..254]), [&block[..], &[0x00]].concat());
+
+        let mut frame = vec![0x00];
+        frame.extend_from_slice(&nonzero[..254]);
+        assert_eq!(cobs(&frame), [&[0x01][..], &block, &[0x00]].concat());
+
+        assert_eq!(cobs(&nonzero), [&block[..], &[0x02, 0xff, 0x00]].concat());
+
+        let mut frame = nonzero[..254].to_vec();
+        frame.push(0x00);
+        assert_eq!(cobs(&frame), [&block[..], &[0x01, 0x01, 0x00]].concat());
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        let frames: Vec<Vec<u8>> = vec![
+            vec![0],
+            vec![0; 600],
+            (0..=255).cycle().take(1000).collect(),
+            (1..=255).cycle().take(254 * 3).collect(),
+            (1..=255).cycle().take(254 * 3 + 1).collect(),
+        ];
+        let mut encoder = CobsEncoder::wrap(Vec::new());
+        for frame in &frames {
+            encoder.write_frame(frame).unwrap();
+        }
+        let encoded = encoder.into_inner();
+
+        let mut decoder = CobsDecoder::wrap(&encoded[..]);
+        for frame in &frames {
+            assert_eq!(decoder.read_frame().unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(decoder.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn cobs_corrupted_block() {
+        let mut decoder = CobsDecoder::wrap(&[0x05, 0x11, 0x22, 0x00, 0x02, 0x33, 0x00][..]);
+        assert_eq!(decoder.read_frame().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.read_frame().unwrap(), Some(vec![0x33]));
+    }
+
+    #[test]
+    fn slip_round_trip() {
+        let frames = [vec![SLIP_END, SLIP_ESC, 0x01, SLIP_ESC, SLIP_END], vec![0xdc, 0xdd]];
+        let config = ByteStuffingConfig { leading_delimiter: true, ..Default::default() };
+        let mut encoder = SlipEncoder::wrap_with(Vec::new(), config);
+        for frame in &frames {
+            encoder.write_frame(frame).unwrap();
+        }
+        let encoded = encoder.into_inner();
+        assert_eq!(encoded[..8], [0xc0, 0xdb, 0xdc, 0xdb, 0xdd, 0x01, 0xdb, 0xdd]);
+
+        let mut decoder = SlipDecoder::wrap(&encoded[..]);
+        for frame in &frames {
+            assert_eq!(decoder.read_frame().unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(decoder.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn slip_bad_escapes() {
+        let input = [0x01, SLIP_ESC, 0x02, SLIP_END, 0x03, SLIP_ESC, SLIP_END, 0x04, SLIP_END];
+        let mut decoder = SlipDecoder::wrap(&input[..]);
+        assert_eq!(decoder.read_frame().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.read_frame().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.read_frame().unwrap(), Some(vec![0x04]));
+        assert_eq!(decoder.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_frames_skipped() {
+        let config = ByteStuffingConfig { max_frame_size: 4, ..Default::default() };
+        let mut input = vec![0x42; 20];
+        input.extend_from_slice(&[SLIP_END, 0x01, SLIP_END]);
+        let mut decoder = SlipDecoder::wrap_with(&input[..], config);
+        assert_eq!(decoder.read_frame().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.read_frame().unwrap(), Some(vec![0x01]));
+
+        let mut encoder = SlipEncoder::wrap_with(Vec::new(), config);
+        let error = encoder.write_frame(&[0; 5]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(encoder.into_inner().is_empty());
+    }
+
+    #[test]
+    fn unlimited_frames() {
+        let config = ByteStuffingConfig { max_frame_size: usize::MAX, ..Default::default() };
+        let mut decoder = CobsDecoder::wrap_with(&[0x02, 0x11, 0x00][..], config);
+        assert_eq!(decoder.read_frame().unwrap(), Some(vec![0x11]));
+        let mut decoder = SlipDecoder::wrap_with(&[0x11, SLIP_END][..], config);
+        assert_eq!(decoder.read_frame().unwrap(), Some(vec![0x11]));
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut decoder = SlipDecoder::wrap(&[0x01, 0x02][..]);
+        assert_eq!(decoder.read_frame().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(decoder.read_frame().unwrap(), None);
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+extern crate adler2;
+extern crate crc32c;
+extern crate xxhash_rust;
+
+use self::xxhash_rust::xxh3::Xxh3Default;
+
+/// The checksums ChecksumWriter and ChecksumReader can frame data with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32C (Castagnoli), with a 4 byte trailer. This is the default.
+    #[default]
+    Crc32c,
+    /// Adler-32, with a 4 byte trailer.
+    Adler32,
+    /// The 64 bit XXH3 hash, with an 8 byte trailer.
+    Xxh3,
+}
+
+impl ChecksumAlgorithm {
+    /// The length of the trailer holding the checksum, in bytes.
+    pub fn trailer_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32c   => 4,
+            ChecksumAlgorithm::Adler32  => 4,
+            ChecksumAlgorithm::Xxh3     => 8,
+        }
+    }
+}
+
+// The running state of a checksum.
+enum Checksum {
+    Crc32c(u32),
+    Adler32(adler2::Adler32),
+    Xxh3(Box<Xxh3Default>),
+}
+
+impl Checksum {
+    fn new(algorithm: ChecksumAlgorithm) -> Checksum {
+        match algorithm {
+            ChecksumAlgorithm::Crc32c   => Checksum::Crc32c(0),
+            ChecksumAlgorithm::Adler32  => Checksum::Adler32(adler2::Adler32::new()),
+            ChecksumAlgorithm::Xxh3     => Checksum::Xxh3(Box::new(Xxh3Default::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            Checksum::Crc32c(ref mut crc)       => *crc = crc32c::crc32c_append(*crc, data),
+            Checksum::Adler32(ref mut adler)    => adler.write_slice(data),
+            Checksum::Xxh3(ref mut xxh3)        => xxh3.update(data),
+        }
+    }
+
+    // The trailer for the data checksummed so far: the checksum in little endian.
+    fn trailer(&self) -> Trailer {
+        let (value, len) = match *self {
+            Checksum::Crc32c(crc)           => (crc as u64, 4),
+            Checksum::Adler32(ref adler)    => (adler.checksum() as u64, 4),
+            Checksum::Xxh3(ref xxh3)        => (xxh3.digest(), 8),
+        };
+        Trailer { bytes: value.to_le_bytes(), len }
+    }
+}
+
+struct Trailer {
+    bytes: [u8; 8],
+    len: usize,
+}
+
+impl Trailer {
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// A WriteAdapter which checksums the data written through it, and appends the checksum as a
+/// trailer when it is unwrapped.
+///
+/// The trailer is the checksum in little endian, 4 or 8 bytes long depending on the algorithm.
+/// It is only written by `try_into_inner` (or `into_inner`); a writer which is dropped leaves
+/// its data without a trailer.
+pub struct ChecksumWriter<W> {
+    writer: W,
+    checksum: Checksum,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for ChecksumWriter<W> {
+    type Config = ChecksumAlgorithm;
+
+    fn wrap_with(writer: W, algorithm: ChecksumAlgorithm) -> Self {
+        ChecksumWriter {
+            writer,
+            checksum: Checksum::new(algorithm),
+            out: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap ChecksumWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        if !self.finished {
+            let trailer = self.checksum.trailer();
+            self.out.extend_from_slice(trailer.as_bytes());
+            self.finished = true;
+        }
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+/// A ReadAdapter which strips the checksum trailer written by ChecksumWriter from the data read
+/// through it, and checks it.
+///
+/// The last bytes read from the inner Read are held back, since they may be the trailer. When
+/// the inner Read reaches its end, the held bytes are compared with the checksum of the data
+/// before them; if they differ, or the input is shorter than a trailer, the read fails with
+/// `ErrorKind::InvalidData` instead of returning 0. Unwrapping before the end fails, because
+/// the held bytes have been read ahead of the reader.
+pub struct ChecksumReader<R> {
+    reader: R,
+    checksum: Checksum,
+    tail: Trailer,
+    tail_len: usize,
+    eof: bool,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let trailer_len = self.tail.len;
+        loop {
+            let n = self.reader.read(buf)?;
+            if n == 0 {
+                self.eof = true;
+                return self.verify();
+            }
+
+            // The data read continues the held tail; hold back its last bytes instead.
+            let total = self.tail_len + n;
+            if total <= trailer_len {
+                self.tail.bytes[self.tail_len..total].copy_from_slice(&buf[..n]);
+                self.tail_len = total;
+                continue;
+            }
+            let amt = total - trailer_len;
+            let mut joined = [0; 16];
+            if n >= trailer_len {
+                joined[..trailer_len].copy_from_slice(&buf[n - trailer_len..n]);
+                buf.copy_within(..n - trailer_len, self.tail_len);
+                buf[..self.tail_len].copy_from_slice(&self.tail.bytes[..self.tail_len]);
+                self.tail.bytes[..trailer_len].copy_from_slice(&joined[..trailer_len]);
+            } else {
+                joined[..self.tail_len].copy_from_slice(&self.tail.bytes[..self.tail_len]);
+                joined[self.tail_len..total].copy_from_slice(&buf[..n]);
+                buf[..amt].copy_from_slice(&joined[..amt]);
+                self.tail.bytes[..trailer_len].copy_from_slice(&joined[amt..total]);
+            }
+            self.tail_len = trailer_len;
+            self.checksum.update(&buf[..amt]);
+            return Ok(amt);
+        }
+    }
+}
+
+impl<R> ChecksumReader<R> {
+    fn verify(&self) -> io::Result<usize> {
+        let error = if self.tail_len < self.tail.len {
+            "input ended before its checksum trailer"
+        } else if self.checksum.trailer().as_bytes() != self.tail.as_bytes() {
+            "checksum mismatch"
+        } else {
+            return Ok(0);
+        };
+        Err(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for ChecksumReader<R> {
+    type Config = ChecksumAlgorithm;
+
+    fn wrap_with(reader: R, algorithm: ChecksumAlgorithm) -> Self {
+        ChecksumReader {
+            reader,
+            checksum: Checksum::new(algorithm),
+            tail: Trailer { bytes: [0; 8], len: algorithm.trailer_len() },
+            tail_len: 0,
+            eof: false,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap ChecksumReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        if self.eof || self.tail_len == 0 {
+            Ok(self.reader)
+        } else {
+            let remaining = self.tail_len;
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io::{self, ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{ChecksumAlgorithm, ChecksumReader, ChecksumWriter};
+
+    const ALGORITHMS: [ChecksumAlgorithm; 3] =
+        [ChecksumAlgorithm::Crc32c, ChecksumAlgorithm::Adler32, ChecksumAlgorithm::Xxh3];
+
+    // Returns at most `step` bytes per read, so the trailer is split across reads.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let amt = cmp::min(self.step, buf.len());
+            (&mut self.data).take(amt as u64).read(buf)
+        }
+    }
+
+    fn checksummed(algorithm: ChecksumAlgorithm, data: &[u8]) -> Vec<u8> {
+        let mut writer = ChecksumWriter::wrap_with(Vec::new(), algorithm);
+        writer.write_all(data).unwrap();
+        writer.into_inner()
+    }
+
+    fn read(algorithm: ChecksumAlgorithm, input: &[u8], step: usize, buf_len: usize)
+        -> io::Result<Vec<u8>>
+    {
+        let mut reader = ChecksumReader::wrap_with(Trickle { data: input, step }, algorithm);
+        let mut data = Vec::new();
+        let mut buf = vec![0; buf_len];
+        loop {
+            match reader.read(&mut buf)? {
+                0   => return Ok(data),
+                n   => data.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_with_split_trailer() {
+        let data: Vec<u8> = (0..100).collect();
+        for &algorithm in &ALGORITHMS {
+            let input = checksummed(algorithm, &data);
+            assert_eq!(input.len(), data.len() + algorithm.trailer_len());
+            for &step in &[1, 3, 5, 7, 64, 1024] {
+                for &buf_len in &[1, 2, 9, 1024] {
+                    assert_eq!(read(algorithm, &input, step, buf_len).unwrap(), data);
+                }
+            }
+            assert_eq!(read(algorithm, &checksummed(algorithm, b""), 1, 1).unwrap(), b"");
+        }
+    }
+
+    #[test]
+    fn corruption_detected() {
+        let data: Vec<u8> = (0..100).collect();
+        for &algorithm in &ALGORITHMS {
+            let input = checksummed(algorithm, &data);
+            for &i in &[0, 50, data.len(), input.len() - 1] {
+                let mut corrupted = input.clone();
+                corrupted[i] ^= 1;
+                let error = read(algorithm, &corrupted, 3, 16).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData);
+            }
+            let error = read(algorithm, &input[..input.len() - 1], 3, 16).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+            let error = read(algorithm, &input[..3], 1, 16).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+extern crate aead;
+extern crate aes_gcm;
+extern crate chacha20poly1305;
+extern crate zeroize;
+
+use self::aead::generic_array::GenericArray;
+use self::aead::rand_core::RngCore;
+use self::aead::stream::{NewStream, StreamBE32, StreamPrimitive};
+use self::aead::{KeyInit, OsRng};
+use self::aes_gcm::Aes256Gcm;
+use self::chacha20poly1305::ChaCha20Poly1305;
+use self::zeroize::{Zeroize, Zeroizing};
+
+// The header is the cipher's id, the chunk size as a little endian u32 and the nonce prefix.
+const HEADER_LEN: usize = 1 + 4 + PREFIX_LEN;
+
+// STREAM takes 5 bytes of the 12 byte nonce for the chunk counter and the last chunk flag.
+const PREFIX_LEN: usize = 7;
+
+const TAG_LEN: usize = 16;
+
+const MAX_CHUNK_SIZE: usize = 1 << 24;
+
+/// The AEAD ciphers EncryptWriter and DecryptReader can use. Both take 256 bit keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    /// ChaCha20-Poly1305. This is the default.
+    #[default]
+    ChaCha20Poly1305,
+    /// AES-256-GCM.
+    Aes256Gcm,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::ChaCha20Poly1305    => 1,
+            Cipher::Aes256Gcm           => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1   => Some(Cipher::ChaCha20Poly1305),
+            2   => Some(Cipher::Aes256Gcm),
+            _   => None,
+        }
+    }
+}
+
+/// The configuration for EncryptWriter and DecryptReader. The default is ChaCha20-Poly1305 in
+/// 64 KiB chunks, without a key.
+///
+/// A key must be set: adapters wrapping a stream without one fail every read, write and unwrap
+/// with `ErrorKind::InvalidInput`. Readers only use the key; they read the cipher and the chunk
+/// size from the header of the stream. The key is zeroed when the configuration is dropped.
+///
+/// The configuration cannot be compared, since a comparison of keys which does not take
+/// constant time leaks how much of them matched.
+///
+/// # Panics
+///
+/// `EncryptWriter::wrap_with` panics if the chunk size is out of range.
+#[derive(Clone)]
+pub struct EncryptionConfig {
+    /// The cipher to encrypt with.
+    pub cipher: Cipher,
+    /// The 256 bit key.
+    pub key: Option<[u8; 32]>,
+    /// The number of bytes of plaintext in each chunk, from 1 byte to 16 MiB.
+    pub chunk_size: usize,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> EncryptionConfig {
+        EncryptionConfig {
+            cipher: Cipher::ChaCha20Poly1305,
+            key: None,
+            chunk_size: 64 * 1024,
+        }
+    }
+}
+
+impl Drop for EncryptionConfig {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+// The STREAM construction over the configured cipher.
+enum Stream {
+    ChaCha20Poly1305(StreamBE32<ChaCha20Poly1305>),
+    Aes256Gcm(Box<StreamBE32<Aes256Gcm>>),
+}
+
+impl Stream {
+    fn new(cipher: Cipher, key: &[u8; 32], prefix: &[u8]) -> Stream {
+        let key = GenericArray::from_slice(key);
+        let prefix = GenericArray::from_slice(prefix);
+        match cipher {
+            Cipher::ChaCha20Poly1305    => {
+                let aead = ChaCha20Poly1305::new(key);
+                Stream::ChaCha20Poly1305(StreamBE32::from_aead(aead, prefix))
+            }
+            Cipher::Aes256Gcm           => {
+                Stream::Aes256Gcm(Box::new(StreamBE32::from_aead(Aes256Gcm::new(key), prefix)))
+            }
+        }
+    }
+
+    fn seal(&self, position: u32, last: bool, header: &[u8], chunk: &mut Vec<u8>)
+        -> aead::Result<()>
+    {
+        match *self {
+            Stream::ChaCha20Poly1305(ref stream)    => {
+                stream.encrypt_in_place(position, last, header, chunk)
+            }
+            Stream::Aes256Gcm(ref stream)           => {
+                stream.encrypt_in_place(position, last, header, chunk)
+            }
+        }
+    }
+
+    fn open(&self, position: u32, last: bool, header: &[u8], chunk: &mut Vec<u8>)
+        -> aead::Result<()>
+    {
+        match *self {
+            Stream::ChaCha20Poly1305(ref stream)    => {
+                stream.decrypt_in_place(position, last, header, chunk)
+            }
+            Stream::Aes256Gcm(ref stream)           => {
+                stream.decrypt_in_place(position, last, header, chunk)
+            }
+        }
+    }
+}
+
+/// A WriteAdapter which encrypts the data written to it with the STREAM construction.
+///
+/// The output starts with a header holding the cipher, the chunk size and a random nonce
+/// prefix. The data follows in chunks, each sealed with the header as associated data and
+/// with its position in the nonce, so chunks cannot be reordered or moved between streams.
+/// The final chunk is sealed as the last one, so truncation is detected; it is only sealed
+/// when the writer is unwrapped, and a writer which is dropped leaves a truncated stream.
+///
+/// Each write fills at most one chunk, and the chunk before it is written out as soon as it is
+/// sealed. Flushing does not seal the chunk being filled.
+pub struct EncryptWriter<W> {
+    writer: W,
+    stream: Option<Stream>,
+    header: [u8; HEADER_LEN],
+    chunk_size: usize,
+    chunk: Vec<u8>,
+    position: u32,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        if !last && self.position == u32::MAX {
+            return Err(io::Error::other("too many chunks for the STREAM counter"));
+        }
+        let stream = self.stream.as_ref().ok_or_else(|| no_key("EncryptWriter"))?;
+        if stream.seal(self.position, last, &self.header, &mut self.chunk).is_err() {
+            return Err(io::Error::other("failed to encrypt a chunk"));
+        }
+        self.out.extend_from_slice(&self.chunk);
+        self.chunk.clear();
+        if !last {
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stream.is_none() {
+            return Err(no_key("EncryptWriter"));
+        }
+        self.flush_out()?;
+        // A full chunk is only sealed once more data shows it is not the last.
+        if self.chunk.len() == self.chunk_size && !buf.is_empty() {
+            self.seal(false)?;
+            self.flush_out()?;
+        }
+        let amt = cmp::min(self.chunk_size - self.chunk.len(), buf.len());
+        self.chunk.extend_from_slice(&buf[..amt]);
+        Ok(amt)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for EncryptWriter<W> {
+    type Config = EncryptionConfig;
+
+    fn wrap_with(writer: W, config: EncryptionConfig) -> Self {
+        assert!(config.chunk_size > 0 && config.chunk_size <= MAX_CHUNK_SIZE,
+                "EncryptWriter chunk size must be from 1 to {} bytes", MAX_CHUNK_SIZE);
+
+        let mut header = [0; HEADER_LEN];
+        header[0] = config.cipher.id();
+        header[1..5].copy_from_slice(&(config.chunk_size as u32).to_le_bytes());
+        OsRng.fill_bytes(&mut header[5..]);
+
+        let stream = config.key.as_ref().map(|key| Stream::new(config.cipher, key, &header[5..]));
+        let out = if stream.is_some() { header.to_vec() } else { Vec::new() };
+        EncryptWriter {
+            writer,
+            stream,
+            header,
+            chunk_size: config.chunk_size,
+            chunk: Vec::with_capacity(config.chunk_size + TAG_LEN),
+            position: 0,
+            out,
+            finished: false,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap EncryptWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        if !self.finished {
+            if let Err(error) = self.seal(true) {
+                let remaining = self.out.len() + self.chunk.len();
+                return Err(UnwrapError::new(self, error, remaining));
+            }
+            self.finished = true;
+        }
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+/// A ReadAdapter which decrypts a stream written by EncryptWriter.
+///
+/// Each chunk is authenticated before any of it is returned. A chunk which fails to
+/// authenticate, because the stream was corrupted, truncated, reordered or encrypted with
+/// another key, fails the read with `ErrorKind::InvalidData`, and so does every read after it.
+///
+/// The input is read a chunk and a byte at a time, to tell whether a chunk is the last one, so
+/// unwrapping fails if the reader holds input or plaintext which has not been read from it
+/// yet.
+pub struct DecryptReader<R> {
+    reader: R,
+    key: Option<Zeroizing<[u8; 32]>>,
+    stream: Option<Stream>,
+    header: [u8; HEADER_LEN],
+    chunk_len: usize,
+    input: Vec<u8>,
+    position: u32,
+    out: Vec<u8>,
+    out_pos: usize,
+    finished: bool,
+    failed: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    fn read_header(&mut self) -> io::Result<()> {
+        let key = self.key.as_ref().ok_or_else(|| no_key("DecryptReader"))?;
+        self.reader.read_exact(&mut self.header)?;
+        let cipher = match Cipher::from_id(self.header[0]) {
+            Some(cipher)    => cipher,
+            None            => {
+                let error = format!("unknown cipher {} in the header", self.header[0]);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+        };
+        let mut chunk_size = [0; 4];
+        chunk_size.copy_from_slice(&self.header[1..5]);
+        let chunk_size = u32::from_le_bytes(chunk_size) as usize;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            let error = format!("invalid chunk size {} in the header", chunk_size);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        self.chunk_len = chunk_size + TAG_LEN;
+        self.stream = Some(Stream::new(cipher, key, &self.header[5..]));
+        Ok(())
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.read_header()?;
+        }
+
+        // Read a byte past a full chunk: a chunk is the last one if the input ends with it.
+        let want = self.chunk_len + 1;
+        while self.input.len() < want {
+            let start = self.input.len();
+            self.input.resize(want, 0);
+            match self.reader.read(&mut self.input[start..]) {
+                Ok(n)       => {
+                    self.input.truncate(start + n);
+                    if n == 0 {
+                        break;
+                    }
+                }
+                Err(error)  => {
+                    self.input.truncate(start);
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        let last = self.input.len() <= self.chunk_len;
+        let len = cmp::min(self.input.len(), self.chunk_len);
+        self.out.clear();
+        self.out.extend_from_slice(&self.input[..len]);
+        self.input.drain(..len);
+        self.out_pos = 0;
+
+        let stream = self.stream.as_ref().unwrap();
+        if stream.open(self.position, last, &self.header, &mut self.out).is_err() {
+            self.out.clear();
+            self.failed = true;
+            let error = format!("chunk {} failed to authenticate", self.position);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        if last {
+            self.finished = true;
+        } else if self.position == u32::MAX {
+            self.failed = true;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many chunks"));
+        } else {
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.out_pos == self.out.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            if self.failed {
+                let error = "the stream failed to authenticate";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+            self.open_next()?;
+        }
+        let amt = (&self.out[self.out_pos..]).read(buf)?;
+        self.out_pos += amt;
+        Ok(amt)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for DecryptReader<R> {
+    type Config = EncryptionConfig;
+
+    fn wrap_with(reader: R, config: EncryptionConfig) -> Self {
+        DecryptReader {
+            reader,
+            key: config.key.map(Zeroizing::new),
+            stream: None,
+            header: [0; HEADER_LEN],
+            chunk_len: 0,
+            input: Vec::new(),
+            position: 0,
+            out: Vec::new(),
+            out_pos: 0,
+            finished: false,
+            failed: false,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap DecryptReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.input.len() + self.out.len() - self.out_pos;
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+fn no_key(adapter: &str) -> io::Error {
+    let error = format!("{} was not configured with a key", adapter);
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{DecryptReader, EncryptWriter, EncryptionConfig, HEADER_LEN, TAG_LEN};
+
+    const CHUNK_SIZE: usize = 16;
+    const CHUNK_LEN: usize = CHUNK_SIZE + TAG_LEN;
+
+    fn config() -> EncryptionConfig {
+        EncryptionConfig { key: Some([7; 32]), chunk_size: CHUNK_SIZE, ..Default::default() }
+    }
+
+    // Encrypt three full chunks and a last one of 4 bytes.
+    fn encrypt() -> (Vec<u8>, Vec<u8>) {
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE as u8 + 4).collect();
+        let mut writer = EncryptWriter::wrap_with(Vec::new(), config());
+        writer.write_all(&data).unwrap();
+        (data, writer.into_inner())
+    }
+
+    fn decrypt(encrypted: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        let mut data = Vec::new();
+        let mut reader = DecryptReader::wrap_with(encrypted, config());
+        reader.read_to_end(&mut data).map_err(|error| error.kind())?;
+        Ok(data)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (data, encrypted) = encrypt();
+        assert_eq!(encrypted.len(), HEADER_LEN + 3 * CHUNK_LEN + 4 + TAG_LEN);
+        assert_eq!(decrypt(&encrypted), Ok(data));
+    }
+
+    #[test]
+    fn sealed_chunks_written_out() {
+        let mut writer = EncryptWriter::wrap_with(Vec::new(), config());
+        writer.write_all(&[0; 3 * CHUNK_SIZE + 1]).unwrap();
+        assert_eq!(writer.get_ref().len(), HEADER_LEN + 3 * CHUNK_LEN);
+    }
+
+    #[test]
+    fn truncation_detected() {
+        let (_, encrypted) = encrypt();
+        assert_eq!(decrypt(&encrypted[..HEADER_LEN + 3 * CHUNK_LEN]), Err(ErrorKind::InvalidData));
+        assert_eq!(decrypt(&encrypted[..encrypted.len() - 1]), Err(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn reordering_detected() {
+        let (_, mut encrypted) = encrypt();
+        let (first, second) = encrypted[HEADER_LEN..].split_at_mut(CHUNK_LEN);
+        first.swap_with_slice(&mut second[..CHUNK_LEN]);
+        assert_eq!(decrypt(&encrypted), Err(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn reads_fail_after_corruption() {
+        let (_, mut encrypted) = encrypt();
+        encrypted[HEADER_LEN + CHUNK_LEN] ^= 1;
+        let mut reader = DecryptReader::wrap_with(&encrypted[..], config());
+        let mut buf = [0; CHUNK_SIZE];
+        assert_eq!(reader.read(&mut buf).unwrap(), CHUNK_SIZE);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn missing_key_fails() {
+        let mut writer = EncryptWriter::wrap(Vec::new());
+        assert_eq!(writer.write(b"data").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(writer.try_into_inner().is_err());
+
+        let mut reader = DecryptReader::wrap(&[0; 64][..]);
+        assert_eq!(reader.read(&mut [0; 16]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+    #[test]
+    #[should_panic(expected = "chunk size must be from 1 to")]
+    fn zero_chunk_size_panics() {
+        let config = EncryptionConfig { chunk_size: 0, ..config() };
+        let _ = EncryptWriter::wrap_with(Vec::new(), config);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::io::{self, IntoInnerError, Write};
+
+#[cfg(not(feature = "std"))]
+extern crate embedded_io;
+
+/// The error an adapter fails to unwrap with: a `std::io::Error`, or only an
+/// `embedded_io::ErrorKind` without std.
+#[cfg(feature = "std")]
+pub type IoError = io::Error;
+
+/// The error an adapter fails to unwrap with: a `std::io::Error`, or only an
+/// `embedded_io::ErrorKind` without std.
+#[cfg(not(feature = "std"))]
+pub type IoError = embedded_io::ErrorKind;
+
+/// The error returned when an adapter fails to unwrap. It holds the adapter, so that no data is
+/// lost and unwrapping can be tried again.
+#[derive(Debug)]
+pub struct UnwrapError<A> {
+    adapter: A,
+    error: IoError,
+    remaining: usize,
+}
+
+impl<A> UnwrapError<A> {
+    /// Construct an UnwrapError from the adapter which failed to unwrap, the error which caused
+    /// it, and the number of bytes the adapter holds which could not be flushed or handed back.
+    pub fn new(adapter: A, error: IoError, remaining: usize) -> UnwrapError<A> {
+        UnwrapError { adapter, error, remaining }
+    }
+
+    /// The error which caused unwrapping to fail.
+    pub fn error(&self) -> &IoError {
+        &self.error
+    }
+
+    /// The number of bytes the adapter holds which were left unflushed or unconsumed.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Get a reference to the adapter which failed to unwrap.
+    pub fn adapter(&self) -> &A {
+        &self.adapter
+    }
+
+    /// Get the adapter which failed to unwrap.
+    pub fn into_inner(self) -> A {
+        self.adapter
+    }
+
+    /// Get the error which caused unwrapping to fail, discarding the adapter.
+    pub fn into_error(self) -> IoError {
+        self.error
+    }
+
+    /// Get the adapter, the error and the number of remaining bytes.
+    pub fn into_parts(self) -> (A, IoError, usize) {
+        (self.adapter, self.error, self.remaining)
+    }
+
+    /// Transform the adapter held by this error, keeping the error and the remaining bytes.
+    pub fn map<B, F: FnOnce(A) -> B>(self, f: F) -> UnwrapError<B> {
+        UnwrapError::new(f(self.adapter), self.error, self.remaining)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> From<IntoInnerError<io::BufWriter<W>>> for UnwrapError<io::BufWriter<W>> {
+    fn from(error: IntoInnerError<io::BufWriter<W>>) -> Self {
+        let (error, writer) = error.into_parts();
+        let remaining = writer.buffer().len();
+        UnwrapError::new(writer, error, remaining)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A> From<UnwrapError<A>> for io::Error {
+    fn from(error: UnwrapError<A>) -> io::Error {
+        error.error
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A> fmt::Display for UnwrapError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to unwrap adapter with {} bytes remaining: {}",
+               self.remaining, self.error)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<A> fmt::Display for UnwrapError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to unwrap adapter with {} bytes remaining: {:?}",
+               self.remaining, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<A: fmt::Debug> Error for UnwrapError<A> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+// Write out the whole buffer, retrying interrupted writes. Whatever was written is drained from
+// the buffer even if a later write fails, so that the rest can be written out again.
+#[cfg(feature = "std")]
+pub fn write_out<W: Write + ?Sized>(writer: &mut W, buf: &mut Vec<u8>) -> io::Result<()> {
+    let mut written = 0;
+    let mut result = Ok(());
+    while written < buf.len() {
+        match writer.write(&buf[written..]) {
+            Ok(0)       => {
+                result = Err(io::Error::new(io::ErrorKind::WriteZero,
+                                            "failed to write the buffered data"));
+                break;
+            }
+            Ok(n)       => written += n,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error)  => {
+                result = Err(error);
+                break;
+            }
+        }
+    }
+    buf.drain(..written);
+    result
+}
@@ -0,0 +1,413 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::mem;
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+/// The length prefixes FramedReader and FramedWriter can delimit frames with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// A 1 byte length.
+    U8,
+    /// A 2 byte length.
+    U16,
+    /// A 4 byte length.
+    U32,
+    /// An 8 byte length.
+    U64,
+    /// An unsigned LEB128 varint, 1 to 10 bytes long.
+    Varint,
+}
+
+impl LengthPrefix {
+    // The width of a fixed width prefix, or the longest a varint can be.
+    fn width(self) -> usize {
+        match self {
+            LengthPrefix::U8        => 1,
+            LengthPrefix::U16       => 2,
+            LengthPrefix::U32       => 4,
+            LengthPrefix::U64       => 8,
+            LengthPrefix::Varint    => 10,
+        }
+    }
+}
+
+/// The byte orders of fixed width length prefixes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    /// The most significant byte first, as in network byte order.
+    Big,
+    /// The least significant byte first.
+    Little,
+}
+
+/// The configuration for FramedReader and FramedWriter. The default is a big endian u32 length
+/// prefix, with frames of up to 16 MiB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FramedConfig {
+    /// The length prefix of each frame.
+    pub prefix: LengthPrefix,
+    /// The byte order of fixed width length prefixes. Varints ignore it.
+    pub endianness: Endianness,
+    /// The largest frame, in bytes, not counting its prefix. Readers fail on larger frames
+    /// before reading them, and writers refuse to write them.
+    pub max_frame_size: usize,
+}
+
+impl Default for FramedConfig {
+    fn default() -> FramedConfig {
+        FramedConfig {
+            prefix: LengthPrefix::U32,
+            endianness: Endianness::Big,
+            max_frame_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A ReadAdapter which reads length prefixed frames from its inner Read.
+///
+/// It reads exactly the bytes of each frame, so nothing past the frame is read ahead. If a
+/// read fails in the middle of a frame, the part read so far is kept, and the next call to
+/// `read_frame` continues it. Unwrapping fails while part of a frame is held; `into_parts`
+/// returns it instead.
+pub struct FramedReader<R> {
+    reader: R,
+    config: FramedConfig,
+    prefix: [u8; 10],
+    prefix_len: usize,
+    frame: Vec<u8>,
+}
+
+impl<R> FramedReader<R> {
+    /// Unwrap this reader, returning the inner Read and the bytes of the frame it was in the
+    /// middle of reading, including its length prefix. These are empty between frames.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        let mut partial = self.prefix[..self.prefix_len].to_vec();
+        partial.extend_from_slice(&self.frame);
+        (self.reader, partial)
+    }
+
+    // The length of the frame, once its prefix has been read.
+    fn frame_len(&self) -> io::Result<Option<usize>> {
+        let prefix = &self.prefix[..self.prefix_len];
+        let len = match self.config.prefix {
+            LengthPrefix::Varint    => {
+                if prefix.last().is_none_or(|byte| byte & 0x80 != 0) {
+                    if prefix.len() == self.prefix.len() {
+                        return Err(invalid("frame length varint is too long"));
+                    }
+                    return Ok(None);
+                }
+                if prefix.len() == 10 && prefix[9] > 1 {
+                    return Err(invalid("frame length varint overflows a u64"));
+                }
+                prefix.iter().enumerate().fold(0, |len, (i, byte)| {
+                    len | (((byte & 0x7f) as u64) << (7 * i))
+                })
+            }
+            _                       => {
+                if prefix.len() < self.config.prefix.width() {
+                    return Ok(None);
+                }
+                match self.config.endianness {
+                    Endianness::Big     => {
+                        prefix.iter().fold(0, |len, &byte| (len << 8) | byte as u64)
+                    }
+                    Endianness::Little  => {
+                        prefix.iter().rev().fold(0, |len, &byte| (len << 8) | byte as u64)
+                    }
+                }
+            }
+        };
+        if len > self.config.max_frame_size as u64 {
+            let error = format!("frame of {} bytes is larger than the maximum of {} bytes",
+                                len, self.config.max_frame_size);
+            return Err(invalid(&error));
+        }
+        Ok(Some(len as usize))
+    }
+}
+
+impl<R: Read> FramedReader<R> {
+    /// Read the next frame. This returns `None` if the input ends between frames, and fails
+    /// with `ErrorKind::UnexpectedEof` if it ends in the middle of one.
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let frame_len = loop {
+            if let Some(len) = self.frame_len()? {
+                break len;
+            }
+            let want = match self.config.prefix {
+                LengthPrefix::Varint    => 1,
+                prefix                  => prefix.width() - self.prefix_len,
+            };
+            let n = match self.reader.read(&mut self.prefix[self.prefix_len..][..want]) {
+                Ok(n)       => n,
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error)  => return Err(error),
+            };
+            if n == 0 {
+                if self.prefix_len == 0 {
+                    return Ok(None);
+                }
+                let error = "input ended in the middle of a frame length";
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+            }
+            self.prefix_len += n;
+        };
+
+        // Grow the frame as it is read, so a bogus length cannot allocate the maximum at once.
+        while self.frame.len() < frame_len {
+            let start = self.frame.len();
+            self.frame.resize(start + cmp::min(frame_len - start, 8 * 1024), 0);
+            match self.reader.read(&mut self.frame[start..]) {
+                Ok(0)       => {
+                    self.frame.truncate(start);
+                    let error = "input ended in the middle of a frame";
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                }
+                Ok(n)       => self.frame.truncate(start + n),
+                Err(error)  => {
+                    self.frame.truncate(start);
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        self.prefix_len = 0;
+        Ok(Some(mem::take(&mut self.frame)))
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for FramedReader<R> {
+    type Config = FramedConfig;
+
+    fn wrap_with(reader: R, config: FramedConfig) -> Self {
+        FramedReader {
+            reader,
+            config,
+            prefix: [0; 10],
+            prefix_len: 0,
+            frame: Vec::new(),
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap FramedReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.prefix_len + self.frame.len();
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes of a frame were read from the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+/// A WriteAdapter which writes length prefixed frames to its inner Write.
+///
+/// If writing a frame fails part way, the rest of it is held, and written before the next frame
+/// or when the writer is flushed or unwrapped.
+pub struct FramedWriter<W> {
+    writer: W,
+    config: FramedConfig,
+    out: Vec<u8>,
+}
+
+impl<W: Write> FramedWriter<W> {
+    /// Write a frame, prefixed with its length. This fails with `ErrorKind::InvalidInput`,
+    /// without writing anything, if the frame is larger than the maximum frame size or than its
+    /// length prefix can hold.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.flush_out()?;
+
+        let len = frame.len() as u64;
+        let width = self.config.prefix.width();
+        let fits = self.config.prefix == LengthPrefix::Varint || width == 8
+                || len >> (8 * width) == 0;
+        if frame.len() > self.config.max_frame_size || !fits {
+            let error = format!("frame of {} bytes is too large to write", frame.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+
+        match (self.config.prefix, self.config.endianness) {
+            (LengthPrefix::Varint, _)       => {
+                let mut len = len;
+                while len >= 0x80 {
+                    self.out.push(len as u8 | 0x80);
+                    len >>= 7;
+                }
+                self.out.push(len as u8);
+            }
+            (_, Endianness::Big)            => {
+                self.out.extend_from_slice(&len.to_be_bytes()[8 - width..]);
+            }
+            (_, Endianness::Little)         => {
+                self.out.extend_from_slice(&len.to_le_bytes()[..width]);
+            }
+        }
+        self.out.extend_from_slice(frame);
+        self.flush_out()
+    }
+
+    /// Flush the inner Write, after writing out any frame held by a failed write.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for FramedWriter<W> {
+    type Config = FramedConfig;
+
+    fn wrap_with(writer: W, config: FramedConfig) -> Self {
+        FramedWriter {
+            writer,
+            config,
+            out: Vec::new(),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap FramedWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+fn invalid(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use {ReadAdapter, WriteAdapter};
+    use super::{Endianness, FramedConfig, FramedReader, FramedWriter, LengthPrefix};
+
+    fn config(prefix: LengthPrefix, endianness: Endianness) -> FramedConfig {
+        FramedConfig { prefix, endianness, ..Default::default() }
+    }
+
+    fn read_error(input: &[u8], config: FramedConfig) -> ErrorKind {
+        FramedReader::wrap_with(input, config).read_frame().unwrap_err().kind()
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames: [&[u8]; 3] = [b"", b"frame", &[0xaa; 255]];
+        for &prefix in &[LengthPrefix::U8, LengthPrefix::U16, LengthPrefix::U32,
+                         LengthPrefix::U64, LengthPrefix::Varint] {
+            for &endianness in &[Endianness::Big, Endianness::Little] {
+                let config = config(prefix, endianness);
+                let mut writer = FramedWriter::wrap_with(Vec::new(), config);
+                for frame in &frames[..] {
+                    writer.write_frame(frame).unwrap();
+                }
+                let result = writer.write_frame(&[0; 256]);
+                assert_eq!(result.is_err(), prefix == LengthPrefix::U8);
+                let written = writer.into_inner();
+
+                let mut reader = FramedReader::wrap_with(&written[..], config);
+                for frame in &frames[..] {
+                    assert_eq!(reader.read_frame().unwrap().as_deref(), Some(*frame));
+                }
+                if prefix != LengthPrefix::U8 {
+                    assert_eq!(reader.read_frame().unwrap(), Some(vec![0; 256]));
+                }
+                assert_eq!(reader.read_frame().unwrap(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn prefix_byte_order() {
+        let mut writer = FramedWriter::wrap_with(Vec::new(), config(LengthPrefix::U16,
+                                                                    Endianness::Little));
+        writer.write_frame(&[0; 0x102]).unwrap();
+        assert_eq!(&writer.into_inner()[..2], &[0x02, 0x01]);
+
+        let mut writer = FramedWriter::wrap_with(Vec::new(), config(LengthPrefix::Varint,
+                                                                    Endianness::Big));
+        writer.write_frame(&[0; 300]).unwrap();
+        assert_eq!(&writer.into_inner()[..2], &[0xac, 0x02]);
+    }
+
+    #[test]
+    fn varint_limits() {
+        let config = FramedConfig { prefix: LengthPrefix::Varint, max_frame_size: usize::MAX,
+                                    ..Default::default() };
+
+        // u64::MAX takes all 10 bytes, so the frame itself is missing.
+        let mut max = vec![0xff; 9];
+        max.push(0x01);
+        assert_eq!(read_error(&max, config), ErrorKind::UnexpectedEof);
+
+        let mut overflow = vec![0xff; 9];
+        overflow.push(0x02);
+        assert_eq!(read_error(&overflow, config), ErrorKind::InvalidData);
+
+        let mut too_long = vec![0x80; 10];
+        too_long.push(0x00);
+        assert_eq!(read_error(&too_long, config), ErrorKind::InvalidData);
+
+        assert_eq!(read_error(&[0x80, 0x80], config), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_frames_rejected() {
+        let config = FramedConfig { max_frame_size: 4, ..Default::default() };
+        assert_eq!(read_error(&[0, 0, 0, 5, 1, 2, 3, 4, 5], config), ErrorKind::InvalidData);
+
+        let mut writer = FramedWriter::wrap_with(Vec::new(), config);
+        let error = writer.write_frame(&[0; 5]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let input = [0, 0, 0, 4, 1, 2];
+        assert_eq!(read_error(&input, FramedConfig::default()), ErrorKind::UnexpectedEof);
+        assert_eq!(read_error(&input[..2], FramedConfig::default()), ErrorKind::UnexpectedEof);
+    }
+}
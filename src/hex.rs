@@ -0,0 +1,276 @@
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+// The length of the lines MIME breaks encoded data into.
+const LINE_LEN: usize = 76;
+
+// The most data one write encodes, so that a large write does not hold all of its encoding at
+// once.
+const MAX_WRITE: usize = 4 * 1024;
+
+/// The configuration for HexEncoder. The default is lowercase, without line breaks. Decoders
+/// accept either case and skip line breaks, so they ignore this configuration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HexConfig {
+    /// Encode with uppercase digits.
+    pub uppercase: bool,
+    /// Break lines with CRLF every 76 columns.
+    pub wrap: bool,
+}
+
+/// A WriteAdapter which hex encodes the data written to it.
+///
+/// Encoded data is held until the next write or flush, and is written when the encoder is
+/// unwrapped. Each write encodes at most 4 KiB of data.
+pub struct HexEncoder<W> {
+    writer: W,
+    config: HexConfig,
+    out: Vec<u8>,
+    column: usize,
+}
+
+impl<W: Write> HexEncoder<W> {
+    fn push(&mut self, digit: u8) {
+        if self.config.wrap && self.column == LINE_LEN {
+            self.out.extend_from_slice(b"\r\n");
+            self.column = 0;
+        }
+        self.out.push(digit);
+        self.column += 1;
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for HexEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_out()?;
+        let buf = &buf[..cmp::min(buf.len(), MAX_WRITE)];
+        let digits = if self.config.uppercase { b"0123456789ABCDEF" } else { b"0123456789abcdef" };
+        for &byte in buf {
+            self.push(digits[(byte >> 4) as usize]);
+            self.push(digits[(byte & 0xf) as usize]);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for HexEncoder<W> {
+    type Config = HexConfig;
+
+    fn wrap_with(writer: W, config: HexConfig) -> Self {
+        HexEncoder {
+            writer,
+            config,
+            out: Vec::new(),
+            column: 0,
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap HexEncoder: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+/// A ReadAdapter which decodes hex read from its inner Read.
+///
+/// The input is read ahead in chunks, so unwrapping fails if the decoder holds input which has
+/// not been decoded and read from it yet.
+pub struct HexDecoder<R> {
+    reader: R,
+    input: Box<[u8]>,
+    in_pos: usize,
+    in_len: usize,
+    high: Option<u8>,
+}
+
+impl<R: Read> Read for HexDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.in_pos < self.in_len {
+                let digit = self.input[self.in_pos];
+                self.in_pos += 1;
+                let value = match digit {
+                    b'\r' | b'\n'   => continue,
+                    b'0'..=b'9'     => digit - b'0',
+                    b'a'..=b'f'     => digit - b'a' + 10,
+                    b'A'..=b'F'     => digit - b'A' + 10,
+                    _               => {
+                        // The invalid digit stays unread, so every later read fails on it,
+                        // after the data decoded before it has been returned.
+                        self.in_pos -= 1;
+                        if n > 0 {
+                            break;
+                        }
+                        let error = format!("invalid hex digit {:#04x}", digit);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                    }
+                };
+                match self.high.take() {
+                    Some(high)  => {
+                        buf[n] = high << 4 | value;
+                        n += 1;
+                    }
+                    None        => self.high = Some(value),
+                }
+            } else if n > 0 {
+                break;
+            } else {
+                self.in_len = self.reader.read(&mut self.input)?;
+                self.in_pos = 0;
+                if self.in_len == 0 {
+                    if self.high.is_some() {
+                        let error = "hex input ended in the middle of a byte";
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for HexDecoder<R> {
+    type Config = HexConfig;
+
+    fn wrap_with(reader: R, _: HexConfig) -> Self {
+        HexDecoder {
+            reader,
+            input: vec![0; 8 * 1024].into_boxed_slice(),
+            in_pos: 0,
+            in_len: 0,
+            high: None,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap HexDecoder: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.in_len - self.in_pos + self.high.is_some() as usize;
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{HexConfig, HexDecoder, HexEncoder};
+
+    #[test]
+    fn decoded_data_before_invalid_digit() {
+        let mut decoder = HexDecoder::wrap(&b"414243zz"[..]);
+        let mut buf = [0; 16];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ABC");
+        assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_digit_fails_every_retry() {
+        let mut decoder = HexDecoder::wrap(&b"41\r\n42z4344"[..]);
+        let mut buf = [0; 1];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 1);
+        assert_eq!(decoder.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf, *b"B");
+        for _ in 0..3 {
+            assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+
+        // An invalid digit in the middle of a byte fails too.
+        let mut decoder = HexDecoder::wrap(&b"414z"[..]);
+        let mut buf = [0; 16];
+        assert_eq!(decoder.read(&mut buf).unwrap(), 1);
+        assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(decoder.read(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let config = HexConfig { uppercase: true, wrap: true };
+        let mut encoder = HexEncoder::wrap_with(Vec::new(), config);
+        encoder.write_all(&data).unwrap();
+        let encoded = encoder.into_inner();
+        assert_eq!(&encoded[..4], b"0001");
+        assert_eq!(&encoded[76..78], b"\r\n");
+        assert!(encoded.iter().all(|byte| !byte.is_ascii_lowercase()));
+
+        let mut decoded = Vec::new();
+        HexDecoder::wrap(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn truncated_byte() {
+        let mut decoded = Vec::new();
+        let error = HexDecoder::wrap(&b"414"[..]).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(decoded, b"A");
+    }
+
+    #[test]
+    fn large_write_is_encoded_in_parts() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut encoder = HexEncoder::wrap(Vec::new());
+        let n = encoder.write(&data).unwrap();
+        assert_eq!(n, 4 * 1024);
+        encoder.write_all(&data[n..]).unwrap();
+        let encoded = encoder.into_inner();
+
+        let mut decoded = Vec::new();
+        HexDecoder::wrap(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+}
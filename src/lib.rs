@@ -1,46 +1,245 @@
-use std::io::{Read, Write, IntoInnerError};
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+extern crate alloc;
+
+#[cfg(feature = "derive")]
+extern crate io_adapter_derive;
+
+#[cfg(not(any(feature = "std", feature = "embedded-io")))]
+compile_error!("io-adapter needs either the std feature or the embedded-io feature");
+
+#[cfg(feature = "derive")]
+pub use io_adapter_derive::{ReadAdapter, WriteAdapter};
+#[cfg(feature = "std")]
+pub use auto_decompress::{AutoDecompress, Codec};
+#[cfg(feature = "std")]
+pub use base64::{Base64Alphabet, Base64Config, Base64Decoder, Base64Encoder};
+#[cfg(feature = "std")]
+pub use boxed::{BoxedReadAdapter, BoxedReadConfig, BoxedWriteAdapter, BoxedWriteConfig};
+#[cfg(feature = "std")]
+pub use boxed::{DynReadAdapter, DynWriteAdapter};
+#[cfg(feature = "std")]
+pub use buf_stream::{BufStream, BufStreamConfig, ReadHalf, WriteHalf};
+#[cfg(feature = "std")]
+pub use byte_stuffing::{ByteStuffingConfig, CobsDecoder, CobsEncoder, SlipDecoder, SlipEncoder};
+#[cfg(feature = "checksum")]
+pub use checksum::{ChecksumAlgorithm, ChecksumReader, ChecksumWriter};
+#[cfg(feature = "bincode")]
+pub use _bincode::{BincodeDeserializer, BincodeSerializer};
+#[cfg(feature = "cbor")]
+pub use _serde_cbor::{CborDeserializer, CborSerializer};
+#[cfg(feature = "digest")]
+pub use _digest::{HashingReader, HashingWriter, VerifyingReader};
+#[cfg(feature = "encryption")]
+pub use encryption::{Cipher, DecryptReader, EncryptWriter, EncryptionConfig};
+pub use error::{IoError, UnwrapError};
+#[cfg(feature = "std")]
+pub use framed::{Endianness, FramedConfig, FramedReader, FramedWriter, LengthPrefix};
+#[cfg(feature = "std")]
+pub use hex::{HexConfig, HexDecoder, HexEncoder};
+#[cfg(feature = "std")]
+pub use line_endings::{LineEnding, LineEndingCounts, LineEndingMode};
+#[cfg(feature = "std")]
+pub use line_endings::{LineEndingReader, LineEndingWriter};
+#[cfg(feature = "std")]
+pub use records::{RecordConfig, RecordReader, RecordWriter};
+pub use stack::Stack;
+#[cfg(feature = "std")]
+pub use stack::LayerError;
+#[cfg(feature = "embedded-io")]
+pub use _embedded_io::{EmbeddedBufReader, EmbeddedBufWriter, EmbeddedWriteError};
+#[cfg(feature = "encoding_rs")]
+pub use _encoding_rs::{TranscodeConfig, TranscodeErrors, TranscodeReader, TranscodeWriter};
+#[cfg(feature = "json")]
+pub use _serde_json::{JsonDeserializer, JsonFormat, JsonRecords, JsonSerializer};
+#[cfg(feature = "json")]
+pub use _serde_json::{JsonLinesConfig, JsonLinesWriter};
+#[cfg(feature = "tokio")]
+pub use _tokio::{AsyncReadAdapter, AsyncWriteAdapter, TryIntoInner};
+#[cfg(feature = "zstd")]
+pub use _zstd::ZstdConfig;
+
+#[cfg(feature = "std")]
+mod auto_decompress;
+#[cfg(feature = "std")]
+mod base64;
+#[cfg(feature = "std")]
+mod boxed;
+#[cfg(feature = "std")]
+mod buf_stream;
+#[cfg(feature = "std")]
+mod byte_stuffing;
+#[cfg(feature = "checksum")]
+mod checksum;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod error;
+#[cfg(feature = "std")]
+mod framed;
+#[cfg(feature = "std")]
+mod hex;
+#[cfg(feature = "std")]
+mod line_endings;
+#[cfg(feature = "std")]
+mod records;
+mod stack;
+#[cfg(feature = "bincode")]
+mod _bincode;
+#[cfg(feature = "digest")]
+mod _digest;
+#[cfg(feature = "embedded-io")]
+mod _embedded_io;
+#[cfg(feature = "encoding_rs")]
+mod _encoding_rs;
+#[cfg(feature = "flate2")]
+mod _flate2;
+#[cfg(feature = "msgpack")]
+mod _rmp_serde;
+#[cfg(feature = "cbor")]
+mod _serde_cbor;
+#[cfg(feature = "tokio")]
+mod _tokio;
+#[cfg(feature = "zstd")]
+mod _zstd;
 
 /// Any type which can be adapted over a Read type.
-pub trait ReadAdapter<R: Read> {
-    /// Wrap a Read type in this adapter.
-    fn wrap(reader: R) -> Self;
+///
+/// The Read type is usually a `std::io::Read`, but the trait itself does not require it, so that
+/// adapters can also be implemented over `embedded_io::Read` without std.
+pub trait ReadAdapter<R> {
+    /// The configuration this adapter can be wrapped with. Adapters with nothing to configure
+    /// should use `()`.
+    type Config: Default;
+
+    /// Wrap a Read type in this adapter, using the default configuration.
+    fn wrap(reader: R) -> Self where Self: Sized {
+        Self::wrap_with(reader, Self::Config::default())
+    }
+
+    /// Wrap a Read type in this adapter, using the given configuration.
+    fn wrap_with(reader: R, config: Self::Config) -> Self;
+
+    /// Get a reference to the inner Read.
+    fn get_ref(&self) -> &R;
+
+    /// Get a mutable reference to the inner Read. Reading from it directly may corrupt the
+    /// state of this adapter.
+    fn get_mut(&mut self) -> &mut R;
 
     /// Unwrap this type to get its inner Read. If this action could fail, this call should panic
     /// on fail.
     fn into_inner(self) -> R;
 
-    /// Try to unwrap this type. If this action could fail, it should yield an IntoInnerError if
-    /// it fails. This method is implemented by default on the assumption that into_inner cannot
+    /// Try to unwrap this type. If this action could fail, it should yield an UnwrapError if it
+    /// fails. This method is implemented by default on the assumption that into_inner cannot
     /// fail; if it can, this method needs to be correctly implemented.
-    fn try_into_inner(self) -> Result<R, IntoInnerError<Self>> where Self: Sized {
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> where Self: Sized {
         Ok(self.into_inner())
     }
 }
 
 /// Any type which can be adapted over a Write type.
-pub trait WriteAdapter<W: Write> {
-    /// Wrap a Write type in this adapter.
-    fn wrap(writer: W) -> Self;
+///
+/// The Write type is usually a `std::io::Write`, but the trait itself does not require it, so
+/// that adapters can also be implemented over `embedded_io::Write` without std.
+pub trait WriteAdapter<W> {
+    /// The configuration this adapter can be wrapped with. Adapters with nothing to configure
+    /// should use `()`.
+    type Config: Default;
+
+    /// Wrap a Write type in this adapter, using the default configuration.
+    fn wrap(writer: W) -> Self where Self: Sized {
+        Self::wrap_with(writer, Self::Config::default())
+    }
+
+    /// Wrap a Write type in this adapter, using the given configuration.
+    fn wrap_with(writer: W, config: Self::Config) -> Self;
+
+    /// Get a reference to the inner Write.
+    fn get_ref(&self) -> &W;
+
+    /// Get a mutable reference to the inner Write. Writing to it directly may corrupt the
+    /// state of this adapter.
+    fn get_mut(&mut self) -> &mut W;
 
     /// Unwrap this type to get its inner Write. If this action could fail, this call should panic
     /// on fail.
     fn into_inner(self) -> W;
 
-    /// Try to unwrap this type. If this action could fail, it should yield an IntoInnerError if
-    /// it fails. This method is implemented by default on the assumption that into_inner cannot
+    /// Try to unwrap this type. If this action could fail, it should yield an UnwrapError if it
+    /// fails. This method is implemented by default on the assumption that into_inner cannot
     /// fail; if it can, this method needs to be correctly implemented.
-    fn try_into_inner(self) -> Result<W, IntoInnerError<Self>> where Self: Sized {
+    fn try_into_inner(self) -> Result<W, UnwrapError<Self>> where Self: Sized {
         Ok(self.into_inner())
     }
 }
 
+/// Any type which can be adapted over a type which is both Read and Write, such as a socket.
+pub trait DuplexAdapter<S> {
+    /// The configuration this adapter can be wrapped with. Adapters with nothing to configure
+    /// should use `()`.
+    type Config: Default;
+
+    /// Wrap a stream in this adapter, using the default configuration.
+    fn wrap(stream: S) -> Self where Self: Sized {
+        Self::wrap_with(stream, Self::Config::default())
+    }
+
+    /// Wrap a stream in this adapter, using the given configuration.
+    fn wrap_with(stream: S, config: Self::Config) -> Self;
+
+    /// Get a reference to the inner stream.
+    fn get_ref(&self) -> &S;
+
+    /// Get a mutable reference to the inner stream. Reading from or writing to it directly may
+    /// corrupt the state of this adapter.
+    fn get_mut(&mut self) -> &mut S;
+
+    /// Unwrap this type to get its inner stream. If this action could fail, this call should
+    /// panic on fail.
+    fn into_inner(self) -> S;
+
+    /// Try to unwrap this type. If this action could fail, it should yield an UnwrapError if it
+    /// fails. This method is implemented by default on the assumption that into_inner cannot
+    /// fail; if it can, this method needs to be correctly implemented.
+    fn try_into_inner(self) -> Result<S, UnwrapError<Self>> where Self: Sized {
+        Ok(self.into_inner())
+    }
+}
+
+/// The configuration for buffered adapters, such as `BufReader` and `BufWriter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufConfig {
+    /// The capacity of the buffer, in bytes.
+    pub capacity: usize,
+}
+
+impl Default for BufConfig {
+    fn default() -> BufConfig {
+        BufConfig { capacity: 8 * 1024 }
+    }
+}
+
+#[cfg(feature = "std")]
 mod _std {
     use std::io::{self, Read, Write};
-    use {ReadAdapter, WriteAdapter};
+    use {BufConfig, ReadAdapter, WriteAdapter, UnwrapError};
 
     impl<R: Read> ReadAdapter<R> for io::BufReader<R> {
-        fn wrap(reader: R) -> Self {
-            io::BufReader::new(reader)
+        type Config = BufConfig;
+
+        fn wrap_with(reader: R, config: BufConfig) -> Self {
+            io::BufReader::with_capacity(config.capacity, reader)
+        }
+
+        fn get_ref(&self) -> &R {
+            self.get_ref()
+        }
+
+        fn get_mut(&mut self) -> &mut R {
+            self.get_mut()
         }
 
         fn into_inner(self) -> R {
@@ -49,8 +248,18 @@ mod _std {
     }
 
     impl<W: Write> WriteAdapter<W> for io::BufWriter<W> {
-        fn wrap(writer: W) -> Self {
-            io::BufWriter::new(writer)
+        type Config = BufConfig;
+
+        fn wrap_with(writer: W, config: BufConfig) -> Self {
+            io::BufWriter::with_capacity(config.capacity, writer)
+        }
+
+        fn get_ref(&self) -> &W {
+            self.get_ref()
+        }
+
+        fn get_mut(&mut self) -> &mut W {
+            self.get_mut()
         }
 
         fn into_inner(self) -> W {
@@ -61,35 +270,567 @@ mod _std {
         }
 
 
-        fn try_into_inner(self) -> Result<W, io::IntoInnerError<Self>> {
-            Self::into_inner(self)
+        fn try_into_inner(self) -> Result<W, UnwrapError<Self>> {
+            Self::into_inner(self).map_err(UnwrapError::from)
         }
     }
 }
 
+#[cfg(feature = "json")]
 mod _serde_json {
+    use std::cell::Cell;
     use std::io::{self, Read, Write};
-    use {ReadAdapter, WriteAdapter};
+    use std::marker::PhantomData;
+    use {ReadAdapter, WriteAdapter, UnwrapError};
+    use error::write_out;
 
+    extern crate serde;
     extern crate serde_json as json;
 
+    use self::json::ser::PrettyFormatter;
+
+    /// The output format of a JsonSerializer.
+    #[derive(Clone, Debug, Default, PartialEq, Eq)]
+    pub enum JsonFormat {
+        /// Compact JSON, without any whitespace. This is the default.
+        #[default]
+        Compact,
+        /// Pretty printed JSON, with each level of nesting indented by the given bytes, such as
+        /// `b"  ".to_vec()`.
+        Pretty(Vec<u8>),
+    }
+
+    /// A JSON serializer over a Write type, which can be unwrapped to get the Write back.
+    ///
+    /// `serde_json::Serializer` also implements WriteAdapter, always writing compact JSON, but it
+    /// does not give access to its writer: its `get_ref` and `get_mut` panic. Prefer this type.
+    pub struct JsonSerializer<W> {
+        writer: W,
+        format: JsonFormat,
+    }
+
+    impl<W: Write> JsonSerializer<W> {
+        /// Serialize a value as JSON to the writer.
+        pub fn serialize<T: serde::Serialize>(&mut self, value: &T) -> json::Result<()> {
+            match self.format {
+                JsonFormat::Compact             => {
+                    value.serialize(&mut json::Serializer::new(&mut self.writer))
+                }
+                JsonFormat::Pretty(ref indent)  => {
+                    let formatter = PrettyFormatter::with_indent(indent);
+                    value.serialize(&mut json::Serializer::with_formatter(&mut self.writer,
+                                                                          formatter))
+                }
+            }
+        }
+    }
+
+    impl<W: Write> WriteAdapter<W> for JsonSerializer<W> {
+        type Config = JsonFormat;
+
+        fn wrap_with(writer: W, format: JsonFormat) -> Self {
+            JsonSerializer {
+                writer,
+                format,
+            }
+        }
+
+        fn get_ref(&self) -> &W {
+            &self.writer
+        }
+
+        fn get_mut(&mut self) -> &mut W {
+            &mut self.writer
+        }
+
+        fn into_inner(self) -> W {
+            self.writer
+        }
+    }
+
+    // Kept so code written against serde_json's own Serializer still compiles.
     impl<W: Write> WriteAdapter<W> for json::Serializer<W> {
-        fn wrap(writer: W) -> Self {
+        type Config = ();
+
+        fn wrap_with(writer: W, _: ()) -> Self {
             json::Serializer::new(writer)
         }
 
+        fn get_ref(&self) -> &W {
+            unimplemented!("serde_json::Serializer does not give access to its writer")
+        }
+
+        fn get_mut(&mut self) -> &mut W {
+            unimplemented!("serde_json::Serializer does not give access to its writer")
+        }
+
         fn into_inner(self) -> W {
             self.into_inner()
         }
     }
 
-    impl<R: Read> ReadAdapter<R> for json::Deserializer<io::Bytes<R>> {
-        fn wrap(reader: R) -> Self {
-            json::Deserializer::new(reader.bytes())
+    /// A writer of JSON Lines over a Write type: one compact JSON value per line.
+    ///
+    /// Records are buffered, and written out and flushed once as many records or bytes as
+    /// configured are buffered. Like `BufStream`, only flushing and unwrapping write out the
+    /// buffer; whatever records it holds when the writer is dropped are lost. If unwrapping fails,
+    /// the UnwrapError holds the writer, and `unflushed_records` tells how many records it was
+    /// left holding.
+    pub struct JsonLinesWriter<W: Write> {
+        writer: W,
+        buf: Vec<u8>,
+        records: usize,
+        config: JsonLinesConfig,
+    }
+
+    /// The configuration for JsonLinesWriter. The default flushes once 8 KiB are buffered.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct JsonLinesConfig {
+        /// Flush once this many records are buffered.
+        pub records: Option<usize>,
+        /// Flush once this many bytes are buffered.
+        pub bytes: Option<usize>,
+    }
+
+    impl Default for JsonLinesConfig {
+        fn default() -> JsonLinesConfig {
+            JsonLinesConfig {
+                records: None,
+                bytes: Some(8 * 1024),
+            }
+        }
+    }
+
+    impl<W: Write> JsonLinesWriter<W> {
+        /// Serialize a value as one line of JSON, flushing if the configured limits are reached.
+        ///
+        /// A value which fails to serialize is not written. Once it has been serialized, the
+        /// record is accepted: if the flush fails, it stays buffered and this returns
+        /// `Error::Io`, so the record must not be written again.
+        pub fn write_record<T: serde::Serialize>(&mut self, value: &T) -> json::Result<()> {
+            let len = self.buf.len();
+            if let Err(error) = json::to_writer(&mut self.buf, value) {
+                self.buf.truncate(len);
+                return Err(error);
+            }
+            self.buf.push(b'\n');
+            self.records += 1;
+
+            let records = self.config.records.is_some_and(|limit| self.records >= limit);
+            let bytes = self.config.bytes.is_some_and(|limit| self.buf.len() >= limit);
+            if records || bytes {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        /// Write out all buffered records and flush the writer.
+        pub fn flush(&mut self) -> io::Result<()> {
+            self.flush_buf()?;
+            self.writer.flush()
+        }
+
+        /// The number of records which have not been written out to the writer.
+        pub fn unflushed_records(&self) -> usize {
+            self.records
+        }
+
+        fn flush_buf(&mut self) -> io::Result<()> {
+            let result = write_out(&mut self.writer, &mut self.buf);
+            // Compact JSON never contains a raw newline, so each one left ends a record.
+            self.records = self.buf.iter().filter(|&&byte| byte == b'\n').count();
+            result
+        }
+    }
+
+    impl<W: Write> WriteAdapter<W> for JsonLinesWriter<W> {
+        type Config = JsonLinesConfig;
+
+        fn wrap_with(writer: W, config: JsonLinesConfig) -> Self {
+            JsonLinesWriter {
+                writer,
+                buf: Vec::new(),
+                records: 0,
+                config,
+            }
+        }
+
+        fn get_ref(&self) -> &W {
+            &self.writer
+        }
+
+        fn get_mut(&mut self) -> &mut W {
+            &mut self.writer
+        }
+
+        fn into_inner(self) -> W {
+            match self.try_into_inner() {
+                Ok(writer)  => writer,
+                Err(error)  => panic!("Failed to unwrap JsonLinesWriter: {:?}", error.error()),
+            }
+        }
+
+        fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+            match self.flush() {
+                Ok(())      => Ok(self.writer),
+                Err(error)  => {
+                    let remaining = self.buf.len();
+                    Err(UnwrapError::new(self, error, remaining))
+                }
+            }
+        }
+    }
+
+    /// A JSON deserializer over a Read type, which can be unwrapped to get the Read back.
+    ///
+    /// Parsing a value can require reading one byte past its end (for example, to find the end
+    /// of a number). That byte is held by the deserializer until the next value is parsed, and is
+    /// returned alongside the reader by `into_parts`. Unwrapping while holding it fails.
+    pub struct JsonDeserializer<R> {
+        reader: R,
+        peeked: Option<u8>,
+        read: u64,
+    }
+
+    impl<R: Read> JsonDeserializer<R> {
+        /// Deserialize the next JSON value from the reader.
+        pub fn deserialize<T: serde::Deserialize>(&mut self) -> json::Result<T> {
+            let last = Cell::new(None);
+            let probing = Cell::new(false);
+            let probed = Cell::new(false);
+
+            let (result, end) = {
+                let mut de = json::Deserializer::new(Bytes {
+                    reader: &mut self.reader,
+                    read: &mut self.read,
+                    peeked: self.peeked.take(),
+                    last: &last,
+                    probing: &probing,
+                    probed: &probed,
+                });
+                let result = T::deserialize(&mut de);
+                // The deserializer does not expose the byte it has peeked, if any. Asking it to
+                // check for the end of input while the iterator refuses to yield any more bytes
+                // reveals whether it was holding one. This depends on how serde_json 0.8.6 peeks
+                // in `end`, which is why that release is pinned.
+                probing.set(true);
+                (result, de.end())
+            };
+
+            self.peeked = match end {
+                Ok(())                  => None,
+                Err(_) if probed.get()  => last.get().and_then(|byte| match byte {
+                    b' ' | b'\n' | b'\t' | b'\r'    => Some(byte),
+                    _                               => None,
+                }),
+                Err(_)                  => last.get(),
+            };
+
+            result
+        }
+
+        /// The number of bytes of input consumed so far, not counting the byte read past the end
+        /// of the last value.
+        pub fn offset(&self) -> u64 {
+            self.read - self.peeked.is_some() as u64
+        }
+
+        /// Unwrap this deserializer, returning the reader and the byte which was read past the end
+        /// of the last value, if there was one.
+        pub fn into_parts(self) -> (R, Option<u8>) {
+            (self.reader, self.peeked)
+        }
+
+        // Skip the whitespace before the next value, returning false at the end of the input.
+        fn skip_whitespace(&mut self) -> io::Result<bool> {
+            loop {
+                let byte = match self.peeked.take() {
+                    Some(byte)  => byte,
+                    None        => match read_byte(&mut self.reader)? {
+                        Some(byte)  => {
+                            self.read += 1;
+                            byte
+                        }
+                        None        => return Ok(false),
+                    },
+                };
+                match byte {
+                    b' ' | b'\n' | b'\t' | b'\r'    => continue,
+                    _                               => {
+                        self.peeked = Some(byte);
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+
+    impl<R: Read> ReadAdapter<R> for JsonDeserializer<R> {
+        type Config = ();
+
+        fn wrap_with(reader: R, _: ()) -> Self {
+            JsonDeserializer {
+                reader,
+                peeked: None,
+                read: 0,
+            }
+        }
+
+        fn get_ref(&self) -> &R {
+            &self.reader
+        }
+
+        fn get_mut(&mut self) -> &mut R {
+            &mut self.reader
         }
 
         fn into_inner(self) -> R {
-            unimplemented!()
+            match self.try_into_inner() {
+                Ok(reader)  => reader,
+                Err(error)  => panic!("Failed to unwrap JsonDeserializer: {:?}", error.error()),
+            }
+        }
+
+        fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+            match self.peeked {
+                None        => Ok(self.reader),
+                Some(byte)  => {
+                    let error = format!("byte {:#04x} was read ahead of the reader", byte);
+                    Err(UnwrapError::new(self, io::Error::other(error), 1))
+                }
+            }
+        }
+    }
+
+    /// An iterator of JSON records from a Read type, which can be unwrapped between records to
+    /// get the rest of the input back.
+    ///
+    /// The records can be separated by whitespace, as in newline-delimited JSON, or simply
+    /// concatenated. Each is yielded with the byte offset in the input at which it starts. Like
+    /// JsonDeserializer, this can hold a byte read past the end of the last record, which is
+    /// returned by `into_parts`.
+    ///
+    /// After a record fails to parse, the position in the input is unknown, so the iterator is
+    /// fused and yields no more records.
+    pub struct JsonRecords<R, T> {
+        de: JsonDeserializer<R>,
+        failed: bool,
+        _records: PhantomData<fn() -> T>,
+    }
+
+    impl<R: Read, T> JsonRecords<R, T> {
+        /// The number of bytes of input consumed so far. The rest of the input starts at this
+        /// offset.
+        pub fn offset(&self) -> u64 {
+            self.de.offset()
+        }
+
+        /// Unwrap this iterator, returning the reader and the byte which was read past the end of
+        /// the last record, if there was one.
+        pub fn into_parts(self) -> (R, Option<u8>) {
+            self.de.into_parts()
+        }
+    }
+
+    impl<R: Read, T: serde::Deserialize> Iterator for JsonRecords<R, T> {
+        type Item = json::Result<(u64, T)>;
+
+        fn next(&mut self) -> Option<json::Result<(u64, T)>> {
+            if self.failed {
+                return None;
+            }
+            match self.de.skip_whitespace() {
+                Ok(true)    => {}
+                Ok(false)   => return None,
+                Err(error)  => {
+                    self.failed = true;
+                    return Some(Err(json::Error::from(error)));
+                }
+            }
+            let offset = self.de.offset();
+            let result = self.de.deserialize().map(|record| (offset, record));
+            self.failed = result.is_err();
+            Some(result)
+        }
+    }
+
+    impl<R: Read, T> ReadAdapter<R> for JsonRecords<R, T> {
+        type Config = ();
+
+        fn wrap_with(reader: R, _: ()) -> Self {
+            JsonRecords {
+                de: JsonDeserializer::wrap(reader),
+                failed: false,
+                _records: PhantomData,
+            }
+        }
+
+        fn get_ref(&self) -> &R {
+            self.de.get_ref()
+        }
+
+        fn get_mut(&mut self) -> &mut R {
+            self.de.get_mut()
+        }
+
+        fn into_inner(self) -> R {
+            match self.try_into_inner() {
+                Ok(reader)  => reader,
+                Err(error)  => panic!("Failed to unwrap JsonRecords: {:?}", error.error()),
+            }
+        }
+
+        fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+            let JsonRecords { de, failed, _records } = self;
+            de.try_into_inner().map_err(|error| {
+                error.map(|de| JsonRecords { de, failed, _records })
+            })
+        }
+    }
+
+    struct Bytes<'a, R: 'a> {
+        reader: &'a mut R,
+        read: &'a mut u64,
+        peeked: Option<u8>,
+        last: &'a Cell<Option<u8>>,
+        probing: &'a Cell<bool>,
+        probed: &'a Cell<bool>,
+    }
+
+    impl<'a, R: Read> Iterator for Bytes<'a, R> {
+        type Item = io::Result<u8>;
+
+        fn next(&mut self) -> Option<io::Result<u8>> {
+            if self.probing.get() {
+                self.probed.set(true);
+                return Some(Err(io::Error::other("end of JSON value")));
+            }
+
+            if let Some(byte) = self.peeked.take() {
+                self.last.set(Some(byte));
+                return Some(Ok(byte));
+            }
+
+            match read_byte(self.reader) {
+                Ok(Some(byte))  => {
+                    *self.read += 1;
+                    self.last.set(Some(byte));
+                    Some(Ok(byte))
+                }
+                Ok(None)        => None,
+                Err(error)      => Some(Err(error)),
+            }
+        }
+    }
+
+    fn read_byte<R: Read>(reader: &mut R) -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0)       => return Ok(None),
+                Ok(_)       => return Ok(Some(buf[0])),
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error)  => return Err(error),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::Cursor;
+        use {ReadAdapter, WriteAdapter};
+        use super::{JsonDeserializer, JsonFormat, JsonRecords, JsonSerializer};
+        use super::{JsonLinesConfig, JsonLinesWriter};
+        use super::json::{self, Value};
+
+        #[test]
+        fn format_chosen_by_config() {
+            let formats = [JsonFormat::Compact, JsonFormat::Pretty(b"  ".to_vec())];
+            let outputs: Vec<_> = formats.iter().map(|format| {
+                let mut ser = JsonSerializer::wrap_with(Vec::new(), format.clone());
+                ser.serialize(&vec![1, 2]).unwrap();
+                ser.into_inner()
+            }).collect();
+            assert_eq!(outputs[0], b"[1,2]");
+            assert_eq!(outputs[1], b"[\n  1,\n  2\n]");
+        }
+
+        #[test]
+        fn serde_json_serializer_in_stack() {
+            use std::io::BufWriter;
+            use Stack;
+            use super::serde::Serialize;
+
+            type Json = Stack<json::Serializer<BufWriter<Vec<u8>>>, BufWriter<Vec<u8>>>;
+            let mut stack = Json::wrap(Vec::new());
+            vec![1, 2].serialize(stack.outer_mut().unwrap()).unwrap();
+            assert_eq!(stack.into_inner(), b"[1,2]");
+        }
+
+        #[test]
+        fn lines_lost_on_drop() {
+            let mut out = Vec::new();
+            {
+                let mut writer = JsonLinesWriter::wrap(&mut out);
+                writer.write_record(&1).unwrap();
+                assert_eq!(writer.unflushed_records(), 1);
+            }
+            assert!(out.is_empty());
+
+            let mut writer = JsonLinesWriter::wrap(&mut out);
+            writer.write_record(&1).unwrap();
+            writer.into_inner();
+            assert_eq!(out, b"1\n");
+        }
+
+        #[test]
+        fn lines_accepted_when_flush_fails() {
+            let config = JsonLinesConfig { records: Some(1), bytes: None };
+            let mut writer = JsonLinesWriter::wrap_with(Cursor::new([0; 3]), config);
+            writer.write_record(&12).unwrap();
+            match writer.write_record(&34) {
+                Err(json::Error::Io(_)) => {}
+                result                  => panic!("unexpected {:?}", result),
+            }
+            assert_eq!(writer.unflushed_records(), 1);
+            writer.get_mut().set_position(0);
+            writer.flush().unwrap();
+            assert_eq!(writer.into_inner().into_inner(), *b"34\n");
+        }
+
+        #[test]
+        fn records_fuse_after_error() {
+            let input = &b"{\"a\":1}\n]\n{\"b\":2}\n"[..];
+            let mut records = JsonRecords::<_, Value>::wrap(Cursor::new(input));
+            assert_eq!(records.next().unwrap().unwrap().0, 0);
+            assert!(records.next().unwrap().is_err());
+            assert!(records.next().is_none());
+            assert!(records.next().is_none());
+
+            let records = JsonRecords::<_, Value>::wrap(Cursor::new(input));
+            assert_eq!(records.filter_map(Result::ok).count(), 1);
+        }
+
+        #[test]
+        fn unwrap_without_read_ahead() {
+            let mut de = JsonDeserializer::wrap(Cursor::new(&b"[1] rest"[..]));
+            assert_eq!(de.deserialize::<Vec<u64>>().unwrap(), vec![1]);
+            let reader = de.try_into_inner().ok().unwrap();
+            assert_eq!(reader.position(), 3);
+        }
+
+        #[test]
+        fn unwrap_with_read_ahead() {
+            let mut de = JsonDeserializer::wrap(Cursor::new(&b"12,34"[..]));
+            assert_eq!(de.deserialize::<u64>().unwrap(), 12);
+            let error = match de.try_into_inner() {
+                Ok(_)       => panic!("unwrapped while holding a byte"),
+                Err(error)  => error,
+            };
+            assert_eq!(error.remaining(), 1);
+            let (reader, peeked) = error.into_inner().into_parts();
+            assert_eq!(peeked, Some(b','));
+            assert_eq!(reader.position(), 3);
         }
     }
 }
@@ -0,0 +1,393 @@
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+/// The line endings LineEndingReader and LineEndingWriter can convert to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as on Unix.
+    Lf,
+    /// `\r\n`, as on Windows.
+    CrLf,
+    /// `\r`, as on classic Mac OS.
+    Cr,
+}
+
+impl LineEnding {
+    /// The bytes of the line ending.
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf      => b"\n",
+            LineEnding::CrLf    => b"\r\n",
+            LineEnding::Cr      => b"\r",
+        }
+    }
+}
+
+/// What LineEndingReader and LineEndingWriter do with the line endings they see. The default is
+/// to convert them to LF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEndingMode {
+    /// Convert every line ending to this one.
+    Convert(LineEnding),
+    /// Pass the data through unchanged, only counting its line endings.
+    Count,
+}
+
+impl Default for LineEndingMode {
+    fn default() -> LineEndingMode {
+        LineEndingMode::Convert(LineEnding::Lf)
+    }
+}
+
+/// The number of each kind of line ending seen by a LineEndingReader or LineEndingWriter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineEndingCounts {
+    /// The number of LF line endings.
+    pub lf: u64,
+    /// The number of CRLF line endings.
+    pub crlf: u64,
+    /// The number of CR line endings not followed by LF.
+    pub cr: u64,
+}
+
+impl LineEndingCounts {
+    /// Whether more than one kind of line ending has been seen.
+    pub fn is_mixed(&self) -> bool {
+        (self.lf > 0) as u8 + (self.crlf > 0) as u8 + (self.cr > 0) as u8 > 1
+    }
+}
+
+// Finds line endings, including CRLF pairs split between reads or writes.
+struct Scanner {
+    target: Option<LineEnding>,
+    pending_cr: bool,
+    counts: LineEndingCounts,
+}
+
+impl Scanner {
+    fn new(mode: LineEndingMode) -> Scanner {
+        let target = match mode {
+            LineEndingMode::Convert(ending)   => Some(ending),
+            LineEndingMode::Count             => None,
+        };
+        Scanner { target, pending_cr: false, counts: LineEndingCounts::default() }
+    }
+
+    // Scans a byte, pushing what it converts to onto out when converting.
+    fn scan(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if self.pending_cr {
+            self.pending_cr = false;
+            if byte == b'\n' {
+                return self.end(LineEnding::CrLf, out);
+            }
+            self.end(LineEnding::Cr, out);
+        }
+        match byte {
+            b'\r'   => self.pending_cr = true,
+            b'\n'   => self.end(LineEnding::Lf, out),
+            _       => if self.target.is_some() { out.push(byte) },
+        }
+    }
+
+    // Ends the data, so a CR waiting for an LF is a line ending of its own.
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.end(LineEnding::Cr, out);
+        }
+    }
+
+    fn end(&mut self, ending: LineEnding, out: &mut Vec<u8>) {
+        match ending {
+            LineEnding::Lf      => self.counts.lf += 1,
+            LineEnding::CrLf    => self.counts.crlf += 1,
+            LineEnding::Cr      => self.counts.cr += 1,
+        }
+        if let Some(target) = self.target {
+            out.extend_from_slice(target.as_bytes());
+        }
+    }
+
+    fn counts(&self) -> LineEndingCounts {
+        let mut counts = self.counts;
+        counts.cr += self.pending_cr as u64;
+        counts
+    }
+}
+
+/// A ReadAdapter which converts the line endings read from its inner Read, or counts them.
+///
+/// When converting, the input is read ahead in chunks, so unwrapping fails if the reader holds
+/// data which has not been read from it yet. A CR at the end of a chunk is held until the next
+/// chunk shows whether it starts a CRLF pair, and counts as held data. When counting, data is
+/// passed through as it is read, and unwrapping always succeeds.
+pub struct LineEndingReader<R> {
+    reader: R,
+    scanner: Scanner,
+    input: Box<[u8]>,
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<R> LineEndingReader<R> {
+    /// The line endings read so far, by kind. A CR at the end of the data read so far counts as
+    /// a lone CR until the next byte shows whether it starts a CRLF pair.
+    pub fn counts(&self) -> LineEndingCounts {
+        self.scanner.counts()
+    }
+}
+
+impl<R: Read> Read for LineEndingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.scanner.target.is_none() {
+            let n = self.reader.read(buf)?;
+            if n == 0 && !buf.is_empty() {
+                self.scanner.finish(&mut self.out);
+            }
+            for &byte in &buf[..n] {
+                self.scanner.scan(byte, &mut self.out);
+            }
+            return Ok(n);
+        }
+
+        while self.out_pos == self.out.len() {
+            self.out.clear();
+            self.out_pos = 0;
+            let n = self.reader.read(&mut self.input)?;
+            if n == 0 {
+                self.scanner.finish(&mut self.out);
+                if self.out.is_empty() {
+                    return Ok(0);
+                }
+            }
+            for &byte in &self.input[..n] {
+                self.scanner.scan(byte, &mut self.out);
+            }
+        }
+        let amt = (&self.out[self.out_pos..]).read(buf)?;
+        self.out_pos += amt;
+        Ok(amt)
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for LineEndingReader<R> {
+    type Config = LineEndingMode;
+
+    fn wrap_with(reader: R, mode: LineEndingMode) -> Self {
+        let input = match mode {
+            LineEndingMode::Convert(_)  => vec![0; 8 * 1024],
+            LineEndingMode::Count       => Vec::new(),
+        };
+        LineEndingReader {
+            reader,
+            scanner: Scanner::new(mode),
+            input: input.into_boxed_slice(),
+            out: Vec::new(),
+            out_pos: 0,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap LineEndingReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let mut remaining = self.out.len() - self.out_pos;
+        if self.scanner.target.is_some() {
+            remaining += self.scanner.pending_cr as usize;
+        }
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+/// A WriteAdapter which converts the line endings written to it, or counts them.
+///
+/// When converting, converted data is held until the next write or flush. A CR at the end of a
+/// write is held until the next write shows whether it starts a CRLF pair; it is written as a
+/// lone CR when the writer is unwrapped. When counting, data is passed through as it is written.
+pub struct LineEndingWriter<W> {
+    writer: W,
+    scanner: Scanner,
+    out: Vec<u8>,
+}
+
+impl<W> LineEndingWriter<W> {
+    /// The line endings written so far, by kind. A CR at the end of the data written so far
+    /// counts as a lone CR until the next byte shows whether it starts a CRLF pair.
+    pub fn counts(&self) -> LineEndingCounts {
+        self.scanner.counts()
+    }
+}
+
+impl<W: Write> LineEndingWriter<W> {
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> Write for LineEndingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.scanner.target.is_none() {
+            let n = self.writer.write(buf)?;
+            for &byte in &buf[..n] {
+                self.scanner.scan(byte, &mut self.out);
+            }
+            return Ok(n);
+        }
+
+        self.flush_out()?;
+        for &byte in buf {
+            self.scanner.scan(byte, &mut self.out);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for LineEndingWriter<W> {
+    type Config = LineEndingMode;
+
+    fn wrap_with(writer: W, mode: LineEndingMode) -> Self {
+        LineEndingWriter {
+            writer,
+            scanner: Scanner::new(mode),
+            out: Vec::new(),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap LineEndingWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        self.scanner.finish(&mut self.out);
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use {ReadAdapter, WriteAdapter};
+    use super::{LineEnding, LineEndingCounts, LineEndingMode, LineEndingReader, LineEndingWriter};
+
+    // A CRLF pair, an LF, a lone CR and a lone CR at the end.
+    const INPUT: &[u8] = b"a\r\nb\nc\rd\r";
+
+    const COUNTS: LineEndingCounts = LineEndingCounts { lf: 1, crlf: 1, cr: 2 };
+
+    // Returns one byte per read, so every CRLF pair is split between reads.
+    struct OneByte<'a>(&'a [u8]);
+
+    impl<'a> Read for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            (&mut self.0).take(1).read(buf)
+        }
+    }
+
+    fn read(mode: LineEndingMode) -> (Vec<u8>, LineEndingCounts) {
+        let mut reader = LineEndingReader::wrap_with(OneByte(INPUT), mode);
+        let mut out = Vec::new();
+        let mut buf = [0; 1];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0   => return (out, reader.counts()),
+                n   => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    fn write(mode: LineEndingMode) -> (Vec<u8>, LineEndingCounts) {
+        let mut writer = LineEndingWriter::wrap_with(Vec::new(), mode);
+        for byte in INPUT.chunks(1) {
+            writer.write_all(byte).unwrap();
+        }
+        writer.flush().unwrap();
+        let counts = writer.counts();
+        (writer.into_inner(), counts)
+    }
+
+    #[test]
+    fn convert_split_pairs() {
+        let expected: [(LineEnding, &[u8]); 3] = [
+            (LineEnding::Lf,    b"a\nb\nc\nd\n"),
+            (LineEnding::CrLf,  b"a\r\nb\r\nc\r\nd\r\n"),
+            (LineEnding::Cr,    b"a\rb\rc\rd\r"),
+        ];
+        for &(ending, output) in &expected {
+            let mode = LineEndingMode::Convert(ending);
+            assert_eq!(read(mode), (output.to_vec(), COUNTS));
+            assert_eq!(write(mode), (output.to_vec(), COUNTS));
+        }
+    }
+
+    #[test]
+    fn count_passes_data_through() {
+        assert_eq!(read(LineEndingMode::Count), (INPUT.to_vec(), COUNTS));
+        assert_eq!(write(LineEndingMode::Count), (INPUT.to_vec(), COUNTS));
+    }
+
+    #[test]
+    fn held_cr() {
+        let mut writer = LineEndingWriter::wrap(Vec::new());
+        writer.write_all(b"a\r").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), b"a");
+        assert_eq!(writer.counts(), LineEndingCounts { cr: 1, ..Default::default() });
+        writer.write_all(b"\n").unwrap();
+        assert_eq!(writer.counts(), LineEndingCounts { crlf: 1, ..Default::default() });
+        assert_eq!(writer.into_inner(), b"a\n");
+
+        let mut reader = LineEndingReader::wrap(&b"a\r"[..]);
+        let mut buf = [0; 1];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(reader.try_into_inner().unwrap_err().remaining(), 1);
+    }
+
+    #[test]
+    fn mixed() {
+        assert!(COUNTS.is_mixed());
+        assert!(!LineEndingCounts::default().is_mixed());
+        assert!(!LineEndingCounts { crlf: 3, ..Default::default() }.is_mixed());
+        assert!(LineEndingCounts { lf: 1, cr: 1, ..Default::default() }.is_mixed());
+    }
+}
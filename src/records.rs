@@ -0,0 +1,436 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+use error::write_out;
+
+/// The configuration for RecordReader and RecordWriter. The default is newline delimited
+/// records of up to 1 MiB, without trimming or escaping.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordConfig {
+    /// The byte or byte sequence ending each record, such as `b"\n"` or `b"\0"`. It must not be
+    /// empty.
+    pub delimiter: Vec<u8>,
+    /// The longest record, in bytes, not counting its delimiter or escape bytes.
+    pub max_record_len: usize,
+    /// Trim a CR from the end of each record read, so CRLF delimited records can be read with
+    /// a `b"\n"` delimiter. Writers ignore this.
+    pub trim_cr: bool,
+    /// The byte escaping the next byte. Writers escape the delimiter in records with it, and
+    /// readers remove it; without one, writers reject records which contain the delimiter. It
+    /// must not be part of the delimiter.
+    pub escape: Option<u8>,
+}
+
+impl Default for RecordConfig {
+    fn default() -> RecordConfig {
+        RecordConfig {
+            delimiter: b"\n".to_vec(),
+            max_record_len: 1024 * 1024,
+            trim_cr: false,
+            escape: None,
+        }
+    }
+}
+
+impl RecordConfig {
+    fn check(&self) {
+        assert!(!self.delimiter.is_empty(), "record delimiter must not be empty");
+        assert!(self.escape.is_none_or(|escape| !self.delimiter.contains(&escape)),
+                "record escape byte must not be part of the delimiter");
+    }
+}
+
+/// A ReadAdapter which splits its input into delimited records.
+///
+/// The input is read ahead in chunks. Unwrapping fails if any of it is buffered; `into_parts`
+/// returns the buffered remainder instead, starting at the current record.
+///
+/// A record longer than the maximum fails with `ErrorKind::InvalidData`, and the rest of it is
+/// skipped, so the next call to `read_record` returns the record after it.
+pub struct RecordReader<R> {
+    reader: R,
+    config: RecordConfig,
+    buf: Vec<u8>,
+    pos: usize,
+    record: Vec<u8>,
+    run: usize,
+    escaped: bool,
+    skipping: bool,
+    eof: bool,
+}
+
+impl<R> RecordReader<R> {
+    /// Unwrap this reader, returning the inner Read and the input it has buffered, from the
+    /// start of the record it was in the middle of reading.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.reader, self.buf)
+    }
+
+    // End the current record, which has been found to be `len` bytes long.
+    fn end_record(&mut self, len: usize) -> io::Result<Option<Vec<u8>>> {
+        let cr = self.config.trim_cr && self.run > self.record.len() - len
+              && self.record[..len].ends_with(b"\r");
+        let len = len - cr as usize;
+        self.record.truncate(len);
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.run = 0;
+
+        let record = mem::take(&mut self.record);
+        if mem::replace(&mut self.skipping, false) {
+            Ok(None)
+        } else if len > self.config.max_record_len {
+            Err(io::Error::new(io::ErrorKind::InvalidData, too_long(self.config.max_record_len)))
+        } else {
+            Ok(Some(record))
+        }
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    /// Read the next record, without its delimiter. This returns `None` at the end of the
+    /// input. The last record does not need a delimiter.
+    pub fn read_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let delimiter_len = self.config.delimiter.len();
+        loop {
+            while self.pos < self.buf.len() {
+                let byte = self.buf[self.pos];
+                self.pos += 1;
+                if self.escaped {
+                    self.escaped = false;
+                    self.record.push(byte);
+                    self.run = 0;
+                } else if Some(byte) == self.config.escape {
+                    self.escaped = true;
+                    continue;
+                } else {
+                    // Only a run of unescaped bytes can be a delimiter.
+                    self.record.push(byte);
+                    self.run += 1;
+                    if self.run >= delimiter_len && self.record.ends_with(&self.config.delimiter) {
+                        let len = self.record.len() - delimiter_len;
+                        match self.end_record(len)? {
+                            Some(record)    => return Ok(Some(record)),
+                            None            => continue,
+                        }
+                    }
+                }
+
+                // The record may end in part of its delimiter, so only fail once it is too long
+                // without it.
+                if self.record.len() >= self.config.max_record_len.saturating_add(delimiter_len) {
+                    let keep = self.record.len() - (delimiter_len - 1);
+                    self.record.drain(..keep);
+                    self.buf.drain(..self.pos);
+                    self.pos = 0;
+                    if !mem::replace(&mut self.skipping, true) {
+                        let error = too_long(self.config.max_record_len);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                    }
+                }
+            }
+
+            if self.eof {
+                if self.escaped {
+                    let error = "input ended after an escape byte";
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, error));
+                }
+                if self.buf.is_empty() {
+                    self.skipping = false;
+                    return Ok(None);
+                }
+                let len = self.record.len();
+                match self.end_record(len)? {
+                    Some(record)    => return Ok(Some(record)),
+                    None            => return Ok(None),
+                }
+            }
+
+            let start = self.buf.len();
+            self.buf.resize(start + 8 * 1024, 0);
+            match self.reader.read(&mut self.buf[start..]) {
+                Ok(n)       => {
+                    self.buf.truncate(start + n);
+                    self.eof = n == 0;
+                }
+                Err(error)  => {
+                    self.buf.truncate(start);
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> ReadAdapter<R> for RecordReader<R> {
+    type Config = RecordConfig;
+
+    fn wrap_with(reader: R, config: RecordConfig) -> Self {
+        config.check();
+        RecordReader {
+            reader,
+            config,
+            buf: Vec::new(),
+            pos: 0,
+            record: Vec::new(),
+            run: 0,
+            escaped: false,
+            skipping: false,
+            eof: false,
+        }
+    }
+
+    fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap RecordReader: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let remaining = self.buf.len();
+        if remaining == 0 {
+            Ok(self.reader)
+        } else {
+            let error = format!("{} bytes were read ahead of the reader", remaining);
+            Err(UnwrapError::new(self, io::Error::other(error), remaining))
+        }
+    }
+}
+
+/// A WriteAdapter which writes delimited records to its inner Write.
+///
+/// If writing a record fails part way, the rest of it is held, and written before the next
+/// record or when the writer is flushed or unwrapped.
+pub struct RecordWriter<W> {
+    writer: W,
+    config: RecordConfig,
+    out: Vec<u8>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Write a record, followed by the delimiter.
+    ///
+    /// Wherever the delimiter would start in the record, that byte is escaped, along with any
+    /// escape bytes. Without an escape byte, such records fail with `ErrorKind::InvalidInput`
+    /// instead, as do records longer than the maximum, and nothing is written.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.flush_out()?;
+        if record.len() > self.config.max_record_len {
+            let error = too_long(self.config.max_record_len);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+
+        let start = self.out.len();
+        let delimiter = &self.config.delimiter;
+        for (i, &byte) in record.iter().enumerate() {
+            // The delimiter can also start in the record and end in the delimiter after it.
+            let rest = &record[i..];
+            let overlap = rest.len().min(delimiter.len());
+            let starts_delimiter = rest[..overlap] == delimiter[..overlap]
+                                && delimiter[overlap..] == delimiter[..delimiter.len() - overlap];
+            if starts_delimiter || Some(byte) == self.config.escape {
+                match self.config.escape {
+                    Some(escape)    => self.out.push(escape),
+                    None            => {
+                        self.out.truncate(start);
+                        let error = "record contains the delimiter";
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+                    }
+                }
+            }
+            self.out.push(byte);
+        }
+        self.out.extend_from_slice(delimiter);
+        self.flush_out()
+    }
+
+    /// Flush the inner Write, after writing out any record held by a failed write.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.writer.flush()
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        write_out(&mut self.writer, &mut self.out)
+    }
+}
+
+impl<W: Write> WriteAdapter<W> for RecordWriter<W> {
+    type Config = RecordConfig;
+
+    fn wrap_with(writer: W, config: RecordConfig) -> Self {
+        config.check();
+        RecordWriter {
+            writer,
+            config,
+            out: Vec::new(),
+        }
+    }
+
+    fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap RecordWriter: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(mut self) -> Result<W, UnwrapError<Self>> {
+        match self.flush() {
+            Ok(())      => Ok(self.writer),
+            Err(error)  => {
+                let remaining = self.out.len();
+                Err(UnwrapError::new(self, error, remaining))
+            }
+        }
+    }
+}
+
+fn too_long(max: usize) -> String {
+    format!("record is longer than the maximum of {} bytes", max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, ErrorKind, Read};
+    use {ReadAdapter, WriteAdapter};
+    use super::{RecordConfig, RecordReader, RecordWriter};
+
+    fn read_all(input: &[u8], config: RecordConfig) -> Vec<Result<Vec<u8>, ErrorKind>> {
+        let mut reader = RecordReader::wrap_with(input, config);
+        let mut records = Vec::new();
+        loop {
+            match reader.read_record() {
+                Ok(Some(record))    => records.push(Ok(record)),
+                Ok(None)            => return records,
+                Err(error)          => {
+                    records.push(Err(error.kind()));
+                    if error.kind() == ErrorKind::UnexpectedEof {
+                        return records;
+                    }
+                }
+            }
+        }
+    }
+
+    fn ok(records: &[&[u8]]) -> Vec<Result<Vec<u8>, ErrorKind>> {
+        records.iter().map(|record| Ok(record.to_vec())).collect()
+    }
+
+    #[test]
+    fn unlimited_records() {
+        let config = RecordConfig { max_record_len: usize::MAX, ..Default::default() };
+        assert_eq!(read_all(b"a\nbc\n", config.clone()), ok(&[b"a", b"bc"]));
+
+        let mut writer = RecordWriter::wrap_with(Vec::new(), config);
+        writer.write_record(b"a").unwrap();
+        assert_eq!(writer.into_inner(), b"a\n");
+    }
+
+    #[test]
+    fn multi_byte_delimiter() {
+        let config = RecordConfig { delimiter: b"<>".to_vec(), ..Default::default() };
+        assert_eq!(read_all(b"one<>two<<>>three<>", config), ok(&[b"one", b"two<", b">three"]));
+    }
+
+    #[test]
+    fn final_record_without_delimiter() {
+        assert_eq!(read_all(b"a\nb", RecordConfig::default()), ok(&[b"a", b"b"]));
+
+        let config = RecordConfig { escape: Some(b'\\'), ..Default::default() };
+        assert_eq!(read_all(b"a\nb\\", config), vec![Ok(b"a".to_vec()),
+                                                     Err(ErrorKind::UnexpectedEof)]);
+    }
+
+    #[test]
+    fn trim_cr() {
+        let input = b"a\r\nb\n\r\n";
+        assert_eq!(read_all(input, RecordConfig::default()), ok(&[b"a\r", b"b", b"\r"]));
+
+        let config = RecordConfig { trim_cr: true, ..Default::default() };
+        assert_eq!(read_all(input, config), ok(&[b"a", b"b", b""]));
+
+        // An escaped CR is part of the record.
+        let config = RecordConfig { trim_cr: true, escape: Some(b'\\'), ..Default::default() };
+        assert_eq!(read_all(b"a\\\r\n", config), ok(&[b"a\r"]));
+    }
+
+    #[test]
+    fn oversized_records_skipped() {
+        let config = RecordConfig { max_record_len: 3, ..Default::default() };
+        assert_eq!(read_all(b"abcdefg\nabc\n", config),
+                   vec![Err(ErrorKind::InvalidData), Ok(b"abc".to_vec())]);
+
+        let config = RecordConfig {
+            delimiter: b"<>".to_vec(),
+            max_record_len: 3,
+            ..Default::default()
+        };
+        assert_eq!(read_all(b"abcdef<>abc<>", config),
+                   vec![Err(ErrorKind::InvalidData), Ok(b"abc".to_vec())]);
+    }
+
+    #[test]
+    fn escaped_bytes_count_towards_max() {
+        let config = RecordConfig { max_record_len: 4, escape: Some(b'\\'), ..Default::default() };
+        let mut reader = RecordReader::wrap_with(&b"\\a\\b\\c\\d\\e\\f\nok\n"[..], config);
+        assert_eq!(reader.read_record().unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(reader.read_record().unwrap(), Some(b"ok".to_vec()));
+        assert_eq!(reader.read_record().unwrap(), None);
+
+        // A record of escaped bytes fails before its end is found.
+        let config = RecordConfig { max_record_len: 4, escape: Some(b'\\'), ..Default::default() };
+        let mut reader = RecordReader::wrap_with(io::repeat(b'\\').take(1 << 30), config);
+        assert_eq!(reader.read_record().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn writer_escapes_delimiter() {
+        let config = RecordConfig {
+            delimiter: b"--".to_vec(),
+            escape: Some(b'\\'),
+            ..Default::default()
+        };
+        let records: [&[u8]; 4] = [b"x-", b"a--b", b"\\", b"-"];
+        let mut writer = RecordWriter::wrap_with(Vec::new(), config.clone());
+        for record in &records {
+            writer.write_record(record).unwrap();
+        }
+        let written = writer.into_inner();
+
+        // A dash ending a record would start the delimiter after it, so it is escaped.
+        assert_eq!(&written[..5], b"x\\---");
+        assert_eq!(read_all(&written, config), ok(&records));
+    }
+
+    #[test]
+    fn writer_without_escape_rejects_delimiter() {
+        let config = RecordConfig { delimiter: b"--".to_vec(), ..Default::default() };
+        let mut writer = RecordWriter::wrap_with(Vec::new(), config);
+        for record in &[&b"a--b"[..], b"x-"] {
+            let error = writer.write_record(record).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        writer.write_record(b"x-y").unwrap();
+        assert_eq!(writer.into_inner(), b"x-y--");
+    }
+}
@@ -0,0 +1,277 @@
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use {ReadAdapter, WriteAdapter, UnwrapError};
+#[cfg(not(feature = "std"))]
+use IoError;
+
+/// Two adapters composed into one. `Stack<O, I>` adapts over whatever `I` adapts over, by wrapping
+/// it in `I` and then wrapping that in `O`.
+///
+/// Layers are numbered from the outside in, so the outer adapter is layer 0. Stacks can be nested
+/// in the inner position to compose more than two adapters.
+///
+/// If the inner adapter fails to unwrap, the outer adapter has already been unwrapped, and the
+/// Stack returned in the UnwrapError holds only the inner adapter. Such a Stack can only be
+/// unwrapped; it has no outer adapter to access, and reading or writing through it fails.
+///
+/// With std, the error of a failed unwrap is wrapped in a LayerError saying which layer failed.
+pub struct Stack<O, I> {
+    layer: Layer<O, I>,
+}
+
+enum Layer<O, I> {
+    Outer(O),
+    Inner(I),
+}
+
+impl<O, I> Stack<O, I> {
+    /// Get a reference to the outer adapter, or `None` if it has been unwrapped.
+    pub fn outer(&self) -> Option<&O> {
+        match self.layer {
+            Layer::Outer(ref outer) => Some(outer),
+            Layer::Inner(_)         => None,
+        }
+    }
+
+    /// Get a mutable reference to the outer adapter, or `None` if it has been unwrapped.
+    pub fn outer_mut(&mut self) -> Option<&mut O> {
+        match self.layer {
+            Layer::Outer(ref mut outer) => Some(outer),
+            Layer::Inner(_)             => None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn unwrapped() -> io::Error {
+        io::Error::other("the outer layer of this Stack has been unwrapped")
+    }
+
+    fn outer_failed(error: UnwrapError<O>) -> UnwrapError<Self> {
+        let (outer, error, remaining) = error.into_parts();
+        UnwrapError::new(Stack { layer: Layer::Outer(outer) }, layer_error(error, 0), remaining)
+    }
+
+    fn inner_failed(error: UnwrapError<I>) -> UnwrapError<Self> {
+        let (inner, error, remaining) = error.into_parts();
+        UnwrapError::new(Stack { layer: Layer::Inner(inner) }, layer_error(error, 1), remaining)
+    }
+}
+
+impl<R, O: ReadAdapter<I>, I: ReadAdapter<R>> ReadAdapter<R> for Stack<O, I> {
+    type Config = (O::Config, I::Config);
+
+    fn wrap_with(reader: R, (outer, inner): Self::Config) -> Self {
+        Stack { layer: Layer::Outer(O::wrap_with(I::wrap_with(reader, inner), outer)) }
+    }
+
+    fn get_ref(&self) -> &R {
+        match self.layer {
+            Layer::Outer(ref outer) => outer.get_ref().get_ref(),
+            Layer::Inner(ref inner) => inner.get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        match self.layer {
+            Layer::Outer(ref mut outer) => outer.get_mut().get_mut(),
+            Layer::Inner(ref mut inner) => inner.get_mut(),
+        }
+    }
+
+    fn into_inner(self) -> R {
+        match self.try_into_inner() {
+            Ok(reader)  => reader,
+            Err(error)  => panic!("Failed to unwrap Stack: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<R, UnwrapError<Self>> {
+        let inner = match self.layer {
+            Layer::Outer(outer) => outer.try_into_inner().map_err(Self::outer_failed)?,
+            Layer::Inner(inner) => inner,
+        };
+        inner.try_into_inner().map_err(Self::inner_failed)
+    }
+}
+
+impl<W, O: WriteAdapter<I>, I: WriteAdapter<W>> WriteAdapter<W> for Stack<O, I> {
+    type Config = (O::Config, I::Config);
+
+    fn wrap_with(writer: W, (outer, inner): Self::Config) -> Self {
+        Stack { layer: Layer::Outer(O::wrap_with(I::wrap_with(writer, inner), outer)) }
+    }
+
+    fn get_ref(&self) -> &W {
+        match self.layer {
+            Layer::Outer(ref outer) => outer.get_ref().get_ref(),
+            Layer::Inner(ref inner) => inner.get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        match self.layer {
+            Layer::Outer(ref mut outer) => outer.get_mut().get_mut(),
+            Layer::Inner(ref mut inner) => inner.get_mut(),
+        }
+    }
+
+    fn into_inner(self) -> W {
+        match self.try_into_inner() {
+            Ok(writer)  => writer,
+            Err(error)  => panic!("Failed to unwrap Stack: {:?}", error.error()),
+        }
+    }
+
+    fn try_into_inner(self) -> Result<W, UnwrapError<Self>> {
+        let inner = match self.layer {
+            Layer::Outer(outer) => outer.try_into_inner().map_err(Self::outer_failed)?,
+            Layer::Inner(inner) => inner,
+        };
+        inner.try_into_inner().map_err(Self::inner_failed)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<O: Read, I> Read for Stack<O, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.layer {
+            Layer::Outer(ref mut outer) => outer.read(buf),
+            Layer::Inner(_)             => Err(Self::unwrapped()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<O: Write, I> Write for Stack<O, I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.layer {
+            Layer::Outer(ref mut outer) => outer.write(buf),
+            Layer::Inner(_)             => Err(Self::unwrapped()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.layer {
+            Layer::Outer(ref mut outer) => outer.flush(),
+            Layer::Inner(_)             => Err(Self::unwrapped()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn layer_error(error: io::Error, layer: usize) -> io::Error {
+    LayerError::wrap(error, layer)
+}
+
+#[cfg(not(feature = "std"))]
+fn layer_error(error: IoError, _: usize) -> IoError {
+    error
+}
+
+/// The error of a Stack layer which failed to unwrap. Stacks wrap the io::Error of the failing
+/// layer in a LayerError, keeping its kind.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct LayerError {
+    layer: usize,
+    error: io::Error,
+}
+
+#[cfg(feature = "std")]
+impl LayerError {
+    /// The layer which failed to unwrap, counting the outermost layer as 0.
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// The error the layer failed with.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    // Errors from a nested Stack are already LayerErrors, numbered from the top of that Stack.
+    fn wrap(error: io::Error, layer: usize) -> io::Error {
+        let kind = error.kind();
+        let error = match error.downcast::<LayerError>() {
+            Ok(nested)  => LayerError { layer: layer + nested.layer, error: nested.error },
+            Err(error)  => LayerError { layer, error },
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for LayerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "layer {} failed to unwrap: {}", self.layer, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for LayerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::{self, BufWriter, Write};
+    use {BufConfig, WriteAdapter, Stack};
+    use super::LayerError;
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn failed_layer(error: &io::Error) -> usize {
+        let layer_error = error.get_ref().and_then(|error| error.downcast_ref::<LayerError>());
+        layer_error.expect("not a LayerError").layer()
+    }
+
+    #[test]
+    fn inner_layer_fails() {
+        let mut stack: Stack<BufWriter<BufWriter<Broken>>, BufWriter<Broken>> =
+            WriteAdapter::wrap(Broken);
+        stack.write_all(b"data").unwrap();
+        let error = match stack.try_into_inner() {
+            Ok(_)       => panic!("unwrapped over a broken writer"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.remaining(), 4);
+        assert_eq!(failed_layer(error.error()), 1);
+        let mut stack = error.into_inner();
+        assert!(stack.outer().is_none());
+        assert!(stack.outer_mut().is_none());
+        assert!(stack.write(b"more").is_err());
+    }
+    #[test]
+    fn outer_layer_fails() {
+        // The inner BufWriter has no buffer, so the outer one fails to write its data through.
+        let config = (BufConfig::default(), BufConfig { capacity: 0 });
+        let mut stack: Stack<BufWriter<BufWriter<Broken>>, BufWriter<Broken>> =
+            WriteAdapter::wrap_with(Broken, config);
+        stack.write_all(b"data").unwrap();
+        let error = match stack.try_into_inner() {
+            Ok(_)       => panic!("unwrapped over a broken writer"),
+            Err(error)  => error,
+        };
+        assert_eq!(error.remaining(), 4);
+        assert_eq!(failed_layer(error.error()), 0);
+        let stack = error.into_inner();
+        assert_eq!(stack.outer().unwrap().buffer(), b"data");
+    }
+}